@@ -0,0 +1,43 @@
+"""Prints the cases of `reference_linkage_test` in lib.rs as SciPy's
+`linkage` computes them, to compare with the values in the test.
+
+    pip install scipy numpy
+    python scripts/scipy_reference_linkage.py
+"""
+
+import numpy as np
+import scipy
+from scipy.cluster.hierarchy import linkage
+
+DATA = np.array(
+    [
+        [0.42, 1.87, 3.10],
+        [2.95, 0.33, 1.48],
+        [0.58, 1.61, 2.74],
+        [4.12, 3.96, 0.21],
+        [2.71, 0.92, 1.13],
+        [3.88, 4.35, 0.67],
+        [1.49, 2.26, 2.05],
+        [5.03, 1.17, 3.62],
+    ]
+)
+
+METHODS = [
+    ("Single", "single"),
+    ("Complete", "complete"),
+    ("Average", "average"),
+    ("Weighted", "weighted"),
+    ("Centroid", "centroid"),
+    ("Median", "median"),
+    ("Ward", "ward"),
+]
+
+print(f"// SciPy {scipy.__version__}")
+for name, method in METHODS:
+    print("(")
+    print(f"    LinkageFunction::{name},")
+    print("    vec![")
+    for left, right, height, _ in linkage(DATA, method, metric="euclidean"):
+        print(f"        ({int(left)}, {int(right)}, {float(height)!r}),")
+    print("    ],")
+    print("),")
@@ -1,29 +1,166 @@
 use wasm_bindgen::prelude::*;
 
+use crate::error::ClusteringError;
+use crate::kernels;
+
+/// Metric selector exposed to JavaScript. Parameters that a C-like enum
+/// cannot carry are passed alongside it and combined in
+/// `DistanceMetric::from_kind`.
 #[derive(Debug, Clone, Copy)]
+#[wasm_bindgen(js_name = DistanceMetric)]
+pub enum DistanceKind {
+    Euclidean,
+    SquaredEuclidean,
+    Chebyshev,
+    Pearson,
+    Spearman,
+    Cosine,
+    Manhattan,
+    Canberra,
+    Minkowski,
+}
+
+/// How missing values (NaN) in the clustered profiles are handled.
 #[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NanPolicy {
+    /// Every distance uses the positions where both profiles have a value,
+    /// rescaled to the full profile length, see
+    /// `DistanceMetric::compute_pairwise_complete`.
+    #[default]
+    PairwiseComplete,
+    /// Profiles with any missing value are left out of the tree.
+    DropIncomplete,
+    /// Missing values are replaced by the mean of the observed values of
+    /// their row, whichever axis is clustered.
+    ImputeRowMean,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum DistanceMetric {
     Euclidean,
+    SquaredEuclidean,
     Chebyshev,
+    Pearson,
+    Spearman,
+    Cosine,
+    Manhattan,
+    Canberra,
+    Minkowski { p: f64 },
 }
 
 impl DistanceMetric {
-    pub fn compute(&self, left: &[f64], right: &[f64]) -> Result<f64, String> {
+    pub fn from_kind(
+        kind: DistanceKind,
+        minkowski_p: Option<f64>,
+    ) -> Result<Self, ClusteringError> {
+        Ok(match kind {
+            DistanceKind::Euclidean => DistanceMetric::Euclidean,
+            DistanceKind::SquaredEuclidean => DistanceMetric::SquaredEuclidean,
+            DistanceKind::Chebyshev => DistanceMetric::Chebyshev,
+            DistanceKind::Pearson => DistanceMetric::Pearson,
+            DistanceKind::Spearman => DistanceMetric::Spearman,
+            DistanceKind::Cosine => DistanceMetric::Cosine,
+            DistanceKind::Manhattan => DistanceMetric::Manhattan,
+            DistanceKind::Canberra => DistanceMetric::Canberra,
+            DistanceKind::Minkowski => match minkowski_p {
+                Some(p) if p.is_finite() && p > 0.0 => {
+                    DistanceMetric::Minkowski { p }
+                }
+                Some(p) => {
+                    return Err(ClusteringError::UnsupportedOption(format!(
+                        "Minkowski p must be a positive number, got {p}"
+                    )));
+                }
+                None => {
+                    return Err(ClusteringError::UnsupportedOption(
+                        "Minkowski distance requires p".into(),
+                    ));
+                }
+            },
+        })
+    }
+
+    pub fn compute(
+        &self,
+        left: &[f64],
+        right: &[f64],
+    ) -> Result<f64, ClusteringError> {
         match self {
             DistanceMetric::Euclidean => Euclidean.distance(left, right),
+            DistanceMetric::SquaredEuclidean => {
+                SquaredEuclidean.distance(left, right)
+            }
             DistanceMetric::Chebyshev => Chebyshev.distance(left, right),
+            DistanceMetric::Pearson => Pearson.distance(left, right),
+            DistanceMetric::Spearman => Spearman.distance(left, right),
+            DistanceMetric::Cosine => Cosine.distance(left, right),
+            DistanceMetric::Manhattan => Manhattan.distance(left, right),
+            DistanceMetric::Canberra => Canberra.distance(left, right),
+            DistanceMetric::Minkowski { p } => {
+                Minkowski { p: *p }.distance(left, right)
+            }
         }
     }
+
+    /// Like `compute`, but only over the positions where neither profile is
+    /// NaN. Sums over fewer positions are scaled up to the full length, as
+    /// R's `dist` does, so that distances stay comparable; correlations,
+    /// the cosine and the Chebyshev maximum are left as they are. NaN when
+    /// no position is complete.
+    pub fn compute_pairwise_complete(
+        &self,
+        left: &[f64],
+        right: &[f64],
+    ) -> Result<f64, ClusteringError> {
+        let is_complete = |(x, y): &(&f64, &f64)| !x.is_nan() && !y.is_nan();
+
+        if left.len() != right.len()
+            || left.iter().zip(right.iter()).all(|pair| is_complete(&pair))
+        {
+            return self.compute(left, right);
+        }
+
+        let (left_complete, right_complete): (Vec<f64>, Vec<f64>) = left
+            .iter()
+            .zip(right.iter())
+            .filter(is_complete)
+            .map(|(x, y)| (*x, *y))
+            .unzip();
+
+        if left_complete.is_empty() {
+            return Ok(f64::NAN);
+        }
+
+        let distance = self.compute(&left_complete, &right_complete)?;
+        let scale = left.len() as f64 / left_complete.len() as f64;
+
+        Ok(match self {
+            DistanceMetric::Euclidean => distance * scale.sqrt(),
+            DistanceMetric::SquaredEuclidean
+            | DistanceMetric::Manhattan
+            | DistanceMetric::Canberra => distance * scale,
+            DistanceMetric::Minkowski { p } => distance * scale.powf(p.recip()),
+            DistanceMetric::Chebyshev
+            | DistanceMetric::Pearson
+            | DistanceMetric::Spearman
+            | DistanceMetric::Cosine => distance,
+        })
+    }
 }
 
 pub trait Distance {
-    fn distance(&self, left: &[f64], right: &[f64]) -> Result<f64, String> {
+    fn distance(
+        &self,
+        left: &[f64],
+        right: &[f64],
+    ) -> Result<f64, ClusteringError> {
         if left.len() != right.len() {
-            Err(format!(
-                "Length mismatch: a = {}, b = {}",
-                left.len(),
-                right.len()
-            ))
+            Err(ClusteringError::DimensionMismatch {
+                what: "profile",
+                expected: left.len(),
+                actual: right.len(),
+            })
         } else {
             Ok(self.compute(left, right))
         }
@@ -36,9 +173,15 @@ pub struct Euclidean;
 
 impl Distance for Euclidean {
     fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
-        left.iter()
-            .zip(right.iter())
-            .fold(0.0, |sum, (x, y)| sum + (x - y).powi(2))
+        SquaredEuclidean.compute(left, right).sqrt()
+    }
+}
+
+pub struct SquaredEuclidean;
+
+impl Distance for SquaredEuclidean {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        kernels::squared_euclidean(left, right)
     }
 }
 
@@ -51,3 +194,116 @@ impl Distance for Chebyshev {
             .fold(0.0, |max_diff, (x, y)| max_diff.max((x - y).abs()))
     }
 }
+
+pub struct Manhattan;
+
+impl Distance for Manhattan {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        kernels::manhattan(left, right)
+    }
+}
+
+/// Sum of `|x - y| / (|x| + |y|)`, pairs where both values are zero add
+/// nothing.
+pub struct Canberra;
+
+impl Distance for Canberra {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        left.iter().zip(right.iter()).fold(0.0, |sum, (x, y)| {
+            let denominator = x.abs() + y.abs();
+            if denominator == 0.0 {
+                sum
+            } else {
+                sum + (x - y).abs() / denominator
+            }
+        })
+    }
+}
+
+pub struct Minkowski {
+    pub p: f64,
+}
+
+impl Distance for Minkowski {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        left.iter()
+            .zip(right.iter())
+            .fold(0.0, |sum, (x, y)| sum + (x - y).abs().powf(self.p))
+            .powf(self.p.recip())
+    }
+}
+
+/// `1 - r` where `r` is the Pearson correlation of the two profiles. NaN when
+/// either profile has zero variance.
+pub struct Pearson;
+
+impl Distance for Pearson {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        let length = left.len() as f64;
+        let left_mean = left.iter().sum::<f64>() / length;
+        let right_mean = right.iter().sum::<f64>() / length;
+
+        let (covariance, left_variance, right_variance) =
+            left.iter().zip(right.iter()).fold(
+                (0.0, 0.0, 0.0),
+                |(covariance, left_variance, right_variance), (x, y)| {
+                    let (dx, dy) = (x - left_mean, y - right_mean);
+                    (
+                        covariance + dx * dy,
+                        left_variance + dx * dx,
+                        right_variance + dy * dy,
+                    )
+                },
+            );
+
+        1.0 - covariance / (left_variance * right_variance).sqrt()
+    }
+}
+
+/// `1 - rho` where `rho` is the Pearson correlation of the ranks of the two
+/// profiles, tied values sharing their average rank.
+pub struct Spearman;
+
+impl Distance for Spearman {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        Pearson.compute(&ranks(left), &ranks(right))
+    }
+}
+
+/// `1 - cos(theta)` between the two profiles. NaN when either profile is all
+/// zeros.
+pub struct Cosine;
+
+impl Distance for Cosine {
+    fn compute(&self, left: &[f64], right: &[f64]) -> f64 {
+        let (dot, left_norm, right_norm) = kernels::dot_and_norms(left, right);
+
+        1.0 - dot / (left_norm * right_norm).sqrt()
+    }
+}
+
+/// Fractional ranks starting at 1, ties get the mean of the ranks they span.
+pub(crate) fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+
+        // positions start..end hold equal values, ranks start+1..=end
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+
+        start = end;
+    }
+
+    ranks
+}
@@ -0,0 +1,531 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{tree::HcTree, utils::CondensedMatrix};
+
+/// Variant of the dynamic tree cut (Langfelder, Zhang and Horvath, 2008).
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DynamicCutMethod {
+    /// Bottom-up detection of compact branches followed by a PAM-like
+    /// assignment of the remaining objects, WGCNA's default.
+    Hybrid,
+    /// Top-down splitting driven only by the dendrogram heights.
+    Tree,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct DynamicCutOptions {
+    pub method: DynamicCutMethod,
+    /// Smallest number of objects that forms a module.
+    pub min_module_size: usize,
+    /// Sensitivity from 0 (few, large modules) to 4 (many, small modules).
+    /// The tree variant only distinguishes 0 from the rest.
+    pub deep_split: u8,
+    /// Merges above this height never join objects of a module. Defaults
+    /// to 99% of the range of merge heights.
+    pub cut_height: Option<f64>,
+    /// Whether the hybrid variant assigns objects left outside of every
+    /// module to the closest module of their branch.
+    pub pam_stage: bool,
+}
+
+#[wasm_bindgen]
+impl DynamicCutOptions {
+    /// Defaults of WGCNA's `cutreeDynamic`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(method: DynamicCutMethod) -> Self {
+        DynamicCutOptions {
+            method,
+            min_module_size: 20,
+            deep_split: 1,
+            cut_height: None,
+            pam_stage: true,
+        }
+    }
+}
+
+fn merge_heights(tree: &HcTree) -> Vec<f64> {
+    tree.nodes
+        .iter()
+        .filter(|node| !node.children.is_empty())
+        .map(|node| node.height)
+        .collect()
+}
+
+fn resolve_cut_height(heights: &[f64], options: &DynamicCutOptions) -> f64 {
+    let max_height = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let min_height = heights.iter().copied().fold(f64::INFINITY, f64::min);
+
+    options
+        .cut_height
+        .unwrap_or(0.99 * (max_height - min_height) + min_height)
+        .min(max_height)
+}
+
+/// Numbers modules given as member lists, largest first.
+fn label_modules(
+    number_of_leaves: usize,
+    mut modules: Vec<Vec<usize>>,
+) -> Vec<usize> {
+    // stable, so equally large modules keep the order they were found in
+    modules.sort_by_key(|members| std::cmp::Reverse(members.len()));
+
+    let mut labels: Vec<usize> = vec![0; number_of_leaves];
+    for (index, members) in modules.iter().enumerate() {
+        for &leaf in members {
+            labels[leaf] = index + 1;
+        }
+    }
+
+    labels
+}
+
+/// Leaves in display order and the height at which every two neighbouring
+/// leaves are joined.
+fn dendrogram_profile(tree: &HcTree) -> (Vec<usize>, Vec<f64>) {
+    let mut order: Vec<usize> = Vec::new();
+    let mut gaps: Vec<f64> = Vec::new();
+
+    // (node, height of the parent when the node is not its first child)
+    let mut stack: Vec<(usize, Option<f64>)> = tree
+        .root()
+        .map(|root| (root.id, None))
+        .into_iter()
+        .collect();
+    let mut pending_gap = 0.0;
+
+    while let Some((node_id, gap)) = stack.pop() {
+        if let Some(gap) = gap {
+            pending_gap = gap;
+        }
+
+        let node = &tree.nodes[node_id];
+        if node.children.is_empty() {
+            if !order.is_empty() {
+                gaps.push(pending_gap);
+            }
+            order.push(node_id);
+            continue;
+        }
+
+        for (position, &child_id) in node.children.iter().enumerate().rev() {
+            stack.push((child_id, (position > 0).then_some(node.height)));
+        }
+    }
+
+    (order, gaps)
+}
+
+/// Module label of every leaf in input order: 1 for the largest module, 2
+/// for the next one and so on, 0 for leaves outside of every module.
+///
+/// Tree variant: starts from the branches below the cut height and keeps
+/// splitting every module along the valleys of its height profile, the
+/// runs of neighbours joined below a reference level. The reference is the
+/// mean joining height of the module, or halfway between the mean and the
+/// lowest height when splitting deeply. As the profile follows the child
+/// order of `tree`, it should be called on the tree as displayed.
+pub fn cutree_tree(tree: &HcTree, options: &DynamicCutOptions) -> Vec<usize> {
+    let (order, gaps) = dendrogram_profile(tree);
+    let heights = merge_heights(tree);
+    if heights.is_empty() {
+        return vec![0; order.len()];
+    }
+    let cut_height = resolve_cut_height(&heights, options);
+    let min_module_size = options.min_module_size.max(1);
+
+    let runs_below = |start: usize, end: usize, level: f64| {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut run_start = start;
+        for position in start + 1..end {
+            if gaps[position - 1] > level {
+                runs.push((run_start, position));
+                run_start = position;
+            }
+        }
+        runs.push((run_start, end));
+        runs.retain(|&(start, end)| end - start >= min_module_size);
+        runs
+    };
+
+    let mut stack: Vec<(usize, usize)> = runs_below(0, order.len(), cut_height);
+    let mut modules: Vec<(usize, usize)> = Vec::new();
+
+    while let Some((start, end)) = stack.pop() {
+        if end - start < 2 * min_module_size {
+            modules.push((start, end));
+            continue;
+        }
+
+        let heights = &gaps[start..end - 1];
+        let mean = heights.iter().sum::<f64>() / heights.len() as f64;
+        let min = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let level = if options.deep_split > 0 {
+            (mean + min) / 2.0
+        } else {
+            mean
+        };
+
+        let parts = runs_below(start, end, level);
+        if parts.len() < 2 {
+            modules.push((start, end));
+        } else {
+            stack.extend(parts);
+        }
+    }
+
+    modules.sort_unstable();
+    label_modules(
+        order.len(),
+        modules
+            .into_iter()
+            .map(|(start, end)| order[start..end].to_vec())
+            .collect(),
+    )
+}
+
+/// Branch of the hybrid variant. A basic branch has only been joined by
+/// single objects and branches that failed to be modules themselves; a
+/// composite branch joins two branches that both could be modules.
+struct Branch {
+    is_basic: bool,
+    is_top_basic: bool,
+    /// Failed to be a module only for being too small.
+    fail_size: bool,
+    size: usize,
+    /// Objects of a basic branch in the order they joined it.
+    singletons: Vec<usize>,
+    attach_height: Option<f64>,
+}
+
+struct HybridThresholds {
+    min_module_size: usize,
+    max_abs_core_scatter: f64,
+    min_abs_gap: f64,
+    min_abs_split_height: f64,
+}
+
+impl HybridThresholds {
+    /// Whether a basic branch joined at `height` fails to be a module, and
+    /// whether it fails only for being too small.
+    fn fails(
+        &self,
+        branch: &Branch,
+        scatter: f64,
+        height: f64,
+    ) -> (bool, bool) {
+        let too_small = branch.size < self.min_module_size;
+        let too_scattered = scatter > self.max_abs_core_scatter;
+        let gap_too_small = height - scatter < self.min_abs_gap;
+        let split_too_low = height < self.min_abs_split_height;
+
+        (
+            too_small || too_scattered || gap_too_small || split_too_low,
+            !(too_scattered || gap_too_small),
+        )
+    }
+}
+
+/// Number of the earliest joined objects that make up the core of a basic
+/// branch.
+fn core_size(branch_size: usize, min_module_size: usize) -> usize {
+    let base_core_size = min_module_size as f64 / 2.0 + 1.0;
+
+    if base_core_size < branch_size as f64 {
+        (base_core_size + (branch_size as f64 - base_core_size).sqrt()) as usize
+    } else {
+        branch_size
+    }
+}
+
+/// Average distance between the core objects of a basic branch.
+fn core_scatter(
+    branch: &Branch,
+    distances: &CondensedMatrix,
+    min_module_size: usize,
+) -> f64 {
+    let core = &branch.singletons
+        [..core_size(branch.singletons.len(), min_module_size)];
+    if core.len() < 2 {
+        return 0.0;
+    }
+
+    let mut total = 0.0;
+    for (position, &first) in core.iter().enumerate() {
+        for &second in &core[position + 1..] {
+            total += distances.get(first, second);
+        }
+    }
+
+    2.0 * total / (core.len() * (core.len() - 1)) as f64
+}
+
+fn average_distance(
+    first: &[usize],
+    second: &[usize],
+    distances: &CondensedMatrix,
+) -> f64 {
+    let total: f64 = first
+        .iter()
+        .flat_map(|&i| second.iter().map(move |&j| (i, j)))
+        .map(|(i, j)| distances.get(i, j))
+        .sum();
+
+    total / (first.len() * second.len()) as f64
+}
+
+/// Module labels as in `cutree_tree`, from the hybrid variant following
+/// WGCNA's `cutreeHybrid` with `pamRespectsDendro` and
+/// `respectSmallClusters`.
+///
+/// Merges below the cut height are replayed bottom-up. Whenever two
+/// branches meet, a basic branch that is too small, too scattered at its
+/// core or too close to the merge height is absorbed by the other one;
+/// otherwise both are kept under a composite branch. Basic branches that
+/// are never absorbed and pass the same tests at the height they are last
+/// joined become modules. With `pam_stage`, remaining objects then join the
+/// closest module under the same branch, by average distance, unless it is
+/// further away than the cut height.
+pub fn cutree_hybrid(
+    tree: &HcTree,
+    distances: &CondensedMatrix,
+    options: &DynamicCutOptions,
+) -> Vec<usize> {
+    const MAX_CORE_SCATTER: [f64; 5] = [0.64, 0.73, 0.82, 0.91, 0.95];
+
+    let number_of_leaves = distances.size();
+    let heights = merge_heights(tree);
+    if heights.is_empty() {
+        return vec![0; number_of_leaves];
+    }
+    let cut_height = resolve_cut_height(&heights, options);
+
+    let deep_split = usize::from(options.deep_split.min(4));
+    let max_core_scatter = MAX_CORE_SCATTER[deep_split];
+    let min_gap = (1.0 - max_core_scatter) * 3.0 / 4.0;
+
+    // heights are measured from the lower 5% of merges
+    let mut sorted_heights = heights.clone();
+    sorted_heights.sort_by(f64::total_cmp);
+    let reference_merge =
+        ((sorted_heights.len() as f64 * 0.05).round() as usize).max(1);
+    let reference_height = sorted_heights[reference_merge - 1];
+
+    let thresholds = HybridThresholds {
+        min_module_size: options.min_module_size.max(1),
+        max_abs_core_scatter: reference_height
+            + max_core_scatter * (cut_height - reference_height),
+        min_abs_gap: min_gap * (cut_height - reference_height),
+        min_abs_split_height: reference_height,
+    };
+
+    let mut branches: Vec<Branch> = Vec::new();
+    // branch every node belongs to, `None` for leaves and nodes above the cut
+    let mut node_branches: Vec<Option<usize>> = vec![None; tree.nodes.len()];
+
+    for node in tree.nodes.iter().filter(|node| !node.children.is_empty()) {
+        let &[first, second] = node.children.as_slice() else {
+            panic!("dynamic tree cut needs a binary tree");
+        };
+        let height = node.height;
+        let is_leaf = |node_id: usize| tree.nodes[node_id].children.is_empty();
+
+        if height > cut_height
+            || (!is_leaf(first) && node_branches[first].is_none())
+            || (!is_leaf(second) && node_branches[second].is_none())
+        {
+            continue;
+        }
+
+        node_branches[node.id] =
+            Some(match (is_leaf(first), is_leaf(second)) {
+                (true, true) => {
+                    branches.push(Branch {
+                        is_basic: true,
+                        is_top_basic: true,
+                        fail_size: false,
+                        size: 2,
+                        singletons: vec![first, second],
+                        attach_height: None,
+                    });
+                    branches.len() - 1
+                }
+                (true, false) | (false, true) => {
+                    let (leaf, other) = if is_leaf(first) {
+                        (first, second)
+                    } else {
+                        (second, first)
+                    };
+                    let branch_id =
+                        node_branches[other].expect("checked above");
+                    let branch = &mut branches[branch_id];
+                    // objects joining a composite branch are left to the
+                    // PAM stage
+                    if branch.is_basic {
+                        branch.singletons.push(leaf);
+                    }
+                    branch.size += 1;
+                    branch_id
+                }
+                (false, false) => {
+                    let first_id = node_branches[first].expect("checked above");
+                    let second_id =
+                        node_branches[second].expect("checked above");
+                    let (mut small, mut large) = if branches[first_id].size
+                        <= branches[second_id].size
+                    {
+                        (first_id, second_id)
+                    } else {
+                        (second_id, first_id)
+                    };
+
+                    let scatter = |branch_id: usize| {
+                        let branch = &branches[branch_id];
+                        if branch.is_basic {
+                            core_scatter(
+                                branch,
+                                distances,
+                                thresholds.min_module_size,
+                            )
+                        } else {
+                            0.0
+                        }
+                    };
+                    let basic_fails = |branch_id: usize| {
+                        let branch = &branches[branch_id];
+                        let (fails, fails_size) = thresholds.fails(
+                            branch,
+                            scatter(branch_id),
+                            height,
+                        );
+                        (branch.is_basic && fails).then_some(fails_size)
+                    };
+
+                    let absorbed = match basic_fails(small) {
+                        Some(fails_size) => Some(fails_size),
+                        None => {
+                            let fails = basic_fails(large);
+                            if fails.is_some() {
+                                std::mem::swap(&mut small, &mut large);
+                            }
+                            fails
+                        }
+                    };
+
+                    if let Some(fails_size) = absorbed {
+                        let small_size = branches[small].size;
+                        let small_singletons = {
+                            let small_branch = &mut branches[small];
+                            small_branch.fail_size = fails_size;
+                            small_branch.is_top_basic = false;
+                            small_branch.attach_height = Some(height);
+                            small_branch.singletons.clone()
+                        };
+
+                        // objects of a branch absorbed by a composite branch
+                        // are left to the PAM stage
+                        let large_branch = &mut branches[large];
+                        if large_branch.is_basic {
+                            large_branch.singletons.extend(small_singletons);
+                        }
+                        large_branch.size += small_size;
+                        large
+                    } else {
+                        branches[small].attach_height = Some(height);
+                        branches[large].attach_height = Some(height);
+
+                        branches.push(Branch {
+                            is_basic: false,
+                            is_top_basic: false,
+                            fail_size: false,
+                            size: branches[small].size + branches[large].size,
+                            singletons: Vec::new(),
+                            attach_height: None,
+                        });
+                        branches.len() - 1
+                    }
+                }
+            });
+    }
+
+    let mut modules: Vec<Vec<usize>> = Vec::new();
+    for branch in branches.iter().filter(|branch| branch.is_top_basic) {
+        let scatter =
+            core_scatter(branch, distances, thresholds.min_module_size);
+        let attach_height = branch.attach_height.unwrap_or(cut_height);
+
+        if branch.size >= thresholds.min_module_size
+            && scatter < thresholds.max_abs_core_scatter
+            && attach_height - scatter > thresholds.min_abs_gap
+        {
+            modules.push(branch.singletons.clone());
+        }
+    }
+
+    let mut labels = label_modules(number_of_leaves, modules);
+    if !options.pam_stage {
+        return labels;
+    }
+
+    // the PAM stage only moves objects within a branch below the cut
+    let static_clusters = tree.cut_by_height(cut_height);
+    let module_members: Vec<Vec<usize>> = {
+        let number_of_modules = labels.iter().copied().max().unwrap_or(0);
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); number_of_modules];
+        for (leaf, &label) in labels.iter().enumerate() {
+            if label > 0 {
+                members[label - 1].push(leaf);
+            }
+        }
+        members
+    };
+
+    // objects of branches that were only too small move together, grouped
+    // by the largest such branch they belong to
+    let mut small_branches: Vec<Option<usize>> = vec![None; number_of_leaves];
+    for (branch_id, branch) in branches.iter().enumerate() {
+        if branch.fail_size {
+            for &leaf in &branch.singletons {
+                small_branches[leaf] = Some(branch_id);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new(); branches.len()];
+    for leaf in (0..number_of_leaves).filter(|&leaf| labels[leaf] == 0) {
+        match small_branches[leaf] {
+            Some(branch_id) => groups[branch_id].push(leaf),
+            None => groups.push(vec![leaf]),
+        }
+    }
+    groups.retain(|group| !group.is_empty());
+
+    let mut assignments: Vec<(usize, usize)> = Vec::new();
+    for (group_index, group) in groups.iter().enumerate() {
+        let branch = static_clusters[group[0]];
+        let closest = module_members
+            .iter()
+            .enumerate()
+            .filter(|(_, members)| static_clusters[members[0]] == branch)
+            .map(|(module, members)| {
+                (module, average_distance(group, members, distances))
+            })
+            .min_by(|first, second| first.1.total_cmp(&second.1));
+
+        if let Some((module, distance)) = closest
+            && distance <= cut_height
+        {
+            assignments.push((group_index, module + 1));
+        }
+    }
+
+    // decided on the labels before the PAM stage, then applied together
+    for (group_index, label) in assignments {
+        for &leaf in &groups[group_index] {
+            labels[leaf] = label;
+        }
+    }
+
+    labels
+}
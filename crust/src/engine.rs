@@ -0,0 +1,518 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::{
+    linkage::{ClusterSizes, LinkageFunction},
+    parallel::{chunks, chunks_mut, reduce},
+    utils::CondensedMatrix,
+};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Number of distances a thread scans at least in one go. Shorter scans are
+/// not worth handing to another thread.
+const MIN_SCAN_LENGTH: usize = 4096;
+
+/// A single agglomeration step. `first` and `second` are node ids in the
+/// numbering used by `HcTree`: leaves are `0..n`, the cluster created by the
+/// k-th merge is `n + k`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Merge {
+    pub first: usize,
+    pub second: usize,
+    pub distance: f64,
+}
+
+/// Closest other active cluster for a slot of the working matrix.
+#[derive(Debug, Clone, Copy)]
+struct Neighbor {
+    slot: usize,
+    distance: f64,
+}
+
+struct LanceWilliamsState {
+    distances: CondensedMatrix,
+    node_ids: Vec<usize>,
+    sizes: Vec<usize>,
+    active: Vec<bool>,
+}
+
+impl LanceWilliamsState {
+    fn node_pair(
+        &self,
+        first_slot: usize,
+        second_slot: usize,
+    ) -> (usize, usize) {
+        let first = self.node_ids[first_slot];
+        let second = self.node_ids[second_slot];
+        if first < second {
+            (first, second)
+        } else {
+            (second, first)
+        }
+    }
+
+    /// Orders candidate pairs by distance, then by node ids so that equal
+    /// distances resolve the same way a full scan in node order would.
+    fn precedes(
+        &self,
+        slot: usize,
+        candidate: Neighbor,
+        other_slot: usize,
+        current: Neighbor,
+    ) -> bool {
+        candidate.distance < current.distance
+            || (candidate.distance == current.distance
+                && self.node_pair(slot, candidate.slot)
+                    < self.node_pair(other_slot, current.slot))
+    }
+
+    fn find_neighbor(&self, slot: usize) -> Option<Neighbor> {
+        let mut best: Option<Neighbor> = None;
+
+        for other in 0..self.distances.size() {
+            if other == slot || !self.active[other] {
+                continue;
+            }
+
+            let candidate = Neighbor {
+                slot: other,
+                distance: self.distances.get(slot, other),
+            };
+
+            best = match best {
+                Some(current)
+                    if !self.precedes(slot, candidate, slot, current) =>
+                {
+                    Some(current)
+                }
+                _ => Some(candidate),
+            };
+        }
+
+        best
+    }
+
+    fn closest_pair(
+        &self,
+        neighbors: &[Option<Neighbor>],
+    ) -> Option<(usize, Neighbor)> {
+        let mut best: Option<(usize, Neighbor)> = None;
+
+        for (slot, &neighbor) in neighbors.iter().enumerate() {
+            if !self.active[slot] {
+                continue;
+            }
+            let Some(candidate) = neighbor else {
+                continue;
+            };
+
+            best = match best {
+                Some((best_slot, current))
+                    if !self.precedes(slot, candidate, best_slot, current) =>
+                {
+                    Some((best_slot, current))
+                }
+                _ => Some((slot, candidate)),
+            };
+        }
+
+        best
+    }
+
+    /// Nearest neighbour of `slot` after `first_slot` and `second_slot`
+    /// were merged into `first_slot`, reusing the cached `neighbor` where
+    /// the merge cannot have changed it.
+    fn refreshed_neighbor(
+        &self,
+        slot: usize,
+        neighbor: Option<Neighbor>,
+        first_slot: usize,
+        second_slot: usize,
+    ) -> Option<Neighbor> {
+        if !self.active[slot] || slot == first_slot {
+            return neighbor;
+        }
+
+        let Some(current) = neighbor else {
+            return self.find_neighbor(slot);
+        };
+
+        if current.slot == first_slot || current.slot == second_slot {
+            return self.find_neighbor(slot);
+        }
+
+        let candidate = Neighbor {
+            slot: first_slot,
+            distance: self.distances.get(slot, first_slot),
+        };
+
+        if self.precedes(slot, candidate, slot, current) {
+            Some(candidate)
+        } else {
+            Some(current)
+        }
+    }
+}
+
+/// Agglomerates all observations of `distances`, updating the working matrix
+/// in place with the Lance–Williams recurrence of `linkage` after each merge.
+///
+/// Every slot caches its nearest active neighbour, so a merge only rescans the
+/// rows whose neighbour took part in it. This works for any linkage,
+/// including those whose merge heights are not monotone.
+pub fn lance_williams_linkage(
+    distances: CondensedMatrix,
+    linkage: LinkageFunction,
+) -> Vec<Merge> {
+    lance_williams_linkage_in_chunks(distances, linkage, MIN_SCAN_LENGTH)
+}
+
+/// `lance_williams_linkage` with threads scanning at least `scan_length`
+/// distances in one go.
+pub fn lance_williams_linkage_in_chunks(
+    mut distances: CondensedMatrix,
+    linkage: LinkageFunction,
+    scan_length: usize,
+) -> Vec<Merge> {
+    let number_of_observations = distances.size();
+    distances.map_in_place(|distance| linkage.prepare(distance));
+
+    let mut state = LanceWilliamsState {
+        distances,
+        node_ids: (0..number_of_observations).collect(),
+        sizes: vec![1; number_of_observations],
+        active: vec![true; number_of_observations],
+    };
+
+    // every slot searches its own row, so the searches run side by side,
+    // each thread taking enough rows to scan `scan_length` distances
+    let mut neighbors: Vec<Option<Neighbor>> =
+        vec![None; number_of_observations];
+    chunks_mut(
+        &mut neighbors,
+        scan_length.div_ceil(number_of_observations.max(1)),
+    )
+    .for_each(|(start, chunk)| {
+        for (slot, neighbor) in (start..).zip(chunk) {
+            *neighbor = state.find_neighbor(slot);
+        }
+    });
+
+    let mut merges: Vec<Merge> =
+        Vec::with_capacity(number_of_observations.saturating_sub(1));
+
+    while let Some((first_slot, neighbor)) = state.closest_pair(&neighbors) {
+        let second_slot = neighbor.slot;
+        let (first, second) = state.node_pair(first_slot, second_slot);
+
+        merges.push(Merge {
+            first,
+            second,
+            distance: linkage.finalize(neighbor.distance),
+        });
+
+        // the merged cluster takes over `first_slot`, `second_slot` retires
+        let first_size = state.sizes[first_slot] as f64;
+        let second_size = state.sizes[second_slot] as f64;
+
+        for slot in 0..number_of_observations {
+            if !state.active[slot] || slot == first_slot || slot == second_slot
+            {
+                continue;
+            }
+
+            let updated = linkage.update(
+                state.distances.get(slot, first_slot),
+                state.distances.get(slot, second_slot),
+                neighbor.distance,
+                ClusterSizes {
+                    i: first_size,
+                    j: second_size,
+                    k: state.sizes[slot] as f64,
+                },
+            );
+            state.distances.set(slot, first_slot, updated);
+        }
+
+        state.active[second_slot] = false;
+        neighbors[second_slot] = None;
+        state.sizes[first_slot] += state.sizes[second_slot];
+        state.node_ids[first_slot] = number_of_observations + merges.len() - 1;
+        neighbors[first_slot] = state.find_neighbor(first_slot);
+        // most slots keep their neighbour or compare it with one distance
+        chunks_mut(&mut neighbors, scan_length).for_each(|(start, chunk)| {
+            for (slot, neighbor) in (start..).zip(chunk) {
+                *neighbor = state.refreshed_neighbor(
+                    slot,
+                    *neighbor,
+                    first_slot,
+                    second_slot,
+                );
+            }
+        });
+    }
+
+    merges
+}
+
+/// Agglomerates all observations of `distances` with the nearest-neighbour
+/// chain algorithm. Only valid for reducible linkages, for which it finds
+/// the same hierarchy as `lance_williams_linkage` in O(n²) time.
+///
+/// The chain discovers merges out of height order, so they are sorted
+/// (stably) by height and relabelled to `HcTree` node ids before returning.
+/// Equally close clusters can be merged in another order than
+/// `lance_williams_linkage` merges them, so the chain gives up with `None`
+/// as soon as two candidates are equally close or two merges share a height.
+pub fn nn_chain_linkage(
+    distances: CondensedMatrix,
+    linkage: LinkageFunction,
+) -> Option<Vec<Merge>> {
+    nn_chain_linkage_in_chunks(distances, linkage, MIN_SCAN_LENGTH, true)
+}
+
+/// `nn_chain_linkage` with threads scanning at least `scan_length`
+/// distances in one go, carrying on through ties unless `stop_at_ties`. Of
+/// equally close candidates the tip then keeps the previous chain element,
+/// otherwise the lowest slot, as SciPy does.
+pub fn nn_chain_linkage_in_chunks(
+    mut distances: CondensedMatrix,
+    linkage: LinkageFunction,
+    scan_length: usize,
+    stop_at_ties: bool,
+) -> Option<Vec<Merge>> {
+    let number_of_observations = distances.size();
+    distances.map_in_place(|distance| linkage.prepare(distance));
+
+    let mut sizes: Vec<usize> = vec![1; number_of_observations];
+    let mut chain: Vec<usize> = Vec::with_capacity(number_of_observations);
+    // (retired slot, surviving slot, working distance)
+    let mut slot_merges: Vec<(usize, usize, f64)> =
+        Vec::with_capacity(number_of_observations.saturating_sub(1));
+    // merges of equal height come out in the order the chain found them
+    let mut heights: HashSet<u64> = HashSet::new();
+
+    for _ in 1..number_of_observations {
+        if chain.is_empty() {
+            let first_active = (0..number_of_observations)
+                .find(|&slot| sizes[slot] > 0)
+                .expect("at least two active clusters remain");
+            chain.push(first_active);
+        }
+
+        let (first_slot, second_slot, distance) = loop {
+            let tip = chain[chain.len() - 1];
+            let previous = chain.len().checked_sub(2).map(|i| chain[i]);
+
+            // the lowest active slot among the closest ones, unless the
+            // previous chain element is as close, so that the chain ends
+            let (closest_distance, closest, tied) = reduce(
+                chunks(number_of_observations, scan_length).filter_map(
+                    |slots| {
+                        slots
+                            .filter(|&slot| slot != tip && sizes[slot] > 0)
+                            .map(|slot| (distances.get(tip, slot), slot, false))
+                            .reduce(nearer)
+                    },
+                ),
+                nearer,
+            )
+            .expect("at least two active clusters remain");
+            if tied && stop_at_ties {
+                return None;
+            }
+            let (nearest, nearest_distance) = match previous {
+                Some(previous)
+                    if distances.get(tip, previous) <= closest_distance =>
+                {
+                    (previous, distances.get(tip, previous))
+                }
+                _ => (closest, closest_distance),
+            };
+
+            if Some(nearest) == previous {
+                chain.truncate(chain.len() - 2);
+                break (tip.min(nearest), tip.max(nearest), nearest_distance);
+            }
+
+            chain.push(nearest);
+        };
+
+        // `+ 0.0` turns -0.0 into 0.0
+        if stop_at_ties && !heights.insert((distance + 0.0).to_bits()) {
+            return None;
+        }
+
+        let first_size = sizes[first_slot] as f64;
+        let second_size = sizes[second_slot] as f64;
+
+        for (slot, &size) in sizes.iter().enumerate() {
+            if size == 0 || slot == first_slot || slot == second_slot {
+                continue;
+            }
+
+            let updated = linkage.update(
+                distances.get(slot, first_slot),
+                distances.get(slot, second_slot),
+                distance,
+                ClusterSizes {
+                    i: first_size,
+                    j: second_size,
+                    k: size as f64,
+                },
+            );
+            distances.set(slot, second_slot, updated);
+        }
+
+        sizes[second_slot] += sizes[first_slot];
+        sizes[first_slot] = 0;
+        slot_merges.push((first_slot, second_slot, distance));
+    }
+
+    let slot_merges = slot_merges
+        .into_iter()
+        .map(|(first, second, distance)| {
+            (first, second, linkage.finalize(distance))
+        })
+        .collect();
+    let merges = label_merges(number_of_observations, slot_merges);
+
+    // heights that only coincide once finalized
+    if stop_at_ties
+        && merges
+            .windows(2)
+            .any(|pair| pair[0].distance == pair[1].distance)
+    {
+        return None;
+    }
+
+    Some(merges)
+}
+
+/// The closer of two `(distance, slot, tied)` candidates, the lower slot of
+/// equally close ones, tied when the other one is as close.
+fn nearer(
+    left: (f64, usize, bool),
+    right: (f64, usize, bool),
+) -> (f64, usize, bool) {
+    let (closest, other) = match left
+        .0
+        .partial_cmp(&right.0)
+        .unwrap_or(Ordering::Equal)
+        .then(left.1.cmp(&right.1))
+    {
+        Ordering::Greater => (right, left),
+        _ => (left, right),
+    };
+
+    (closest.0, closest.1, closest.2 || closest.0 == other.0)
+}
+
+/// Single linkage through a minimum spanning tree of `distances` (Prim's
+/// algorithm), needing only O(n) memory on top of the condensed matrix.
+pub fn mst_single_linkage(distances: &CondensedMatrix) -> Vec<Merge> {
+    let number_of_observations = distances.size();
+
+    let mut in_tree: Vec<bool> = vec![false; number_of_observations];
+    // distance from every observation outside the tree to its closest member
+    let mut nearest: Vec<f64> = vec![f64::INFINITY; number_of_observations];
+    let mut edges: Vec<(usize, usize, f64)> =
+        Vec::with_capacity(number_of_observations.saturating_sub(1));
+
+    let mut current = 0;
+
+    for _ in 1..number_of_observations {
+        in_tree[current] = true;
+        let mut next: Option<usize> = None;
+
+        for observation in 0..number_of_observations {
+            if in_tree[observation] {
+                continue;
+            }
+
+            nearest[observation] =
+                nearest[observation].min(distances.get(current, observation));
+
+            next = match next {
+                Some(best) if nearest[best] <= nearest[observation] => {
+                    Some(best)
+                }
+                _ => Some(observation),
+            };
+        }
+
+        let next = next.expect("an observation remains outside the tree");
+        edges.push((current, next, nearest[next]));
+        current = next;
+    }
+
+    label_merges(number_of_observations, edges)
+}
+
+/// Turns merges recorded between observation indices (any member of each
+/// joined cluster) into `HcTree` merges, ordered by height.
+fn label_merges(
+    number_of_observations: usize,
+    mut slot_merges: Vec<(usize, usize, f64)>,
+) -> Vec<Merge> {
+    slot_merges.sort_by(|left, right| left.2.total_cmp(&right.2));
+
+    // slots are observation indices inside the clusters they stand for, so
+    // a union-find over observations recovers the node ids in height order
+    let mut labels = ClusterLabels::new(number_of_observations);
+
+    slot_merges
+        .into_iter()
+        .map(|(first_slot, second_slot, distance)| {
+            let first = labels.find(first_slot);
+            let second = labels.find(second_slot);
+            labels.union(first, second);
+
+            Merge {
+                first: first.min(second),
+                second: first.max(second),
+                distance,
+            }
+        })
+        .collect()
+}
+
+/// Union-find over node ids where every union creates the next node id.
+struct ClusterLabels {
+    parents: Vec<usize>,
+    next_id: usize,
+}
+
+impl ClusterLabels {
+    fn new(number_of_observations: usize) -> Self {
+        ClusterLabels {
+            parents: (0..(2 * number_of_observations).saturating_sub(1))
+                .collect(),
+            next_id: number_of_observations,
+        }
+    }
+
+    fn find(&mut self, node_id: usize) -> usize {
+        let mut root = node_id;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        // path compression
+        let mut current = node_id;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+
+        root
+    }
+
+    fn union(&mut self, first: usize, second: usize) {
+        self.parents[first] = self.next_id;
+        self.parents[second] = self.next_id;
+        self.next_id += 1;
+    }
+}
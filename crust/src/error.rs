@@ -0,0 +1,56 @@
+use core::fmt;
+
+/// Reasons for rejecting the input of a clustering entry point. Crosses the
+/// wasm boundary as a `JsError` carrying the `Display` message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusteringError {
+    /// `what` holds `actual` values where its dimensions call for `expected`.
+    DimensionMismatch {
+        what: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A correlation distance between the rows or columns named by `axis`
+    /// over profiles of `count` values, fewer than two.
+    TooFewObservations { axis: &'static str, count: usize },
+    /// An infinite value in the input matrix. NaN is not an error, it is
+    /// handled according to `NanPolicy`.
+    NonFiniteInput { row: usize, col: usize, value: f64 },
+    /// An option that is missing, out of range or does not fit the others.
+    UnsupportedOption(String),
+    /// `LeafOrdering::Optimal` asked for a tree of `count` leaves, more than
+    /// the `limit` it is allowed to work on.
+    TooManyLeaves { count: usize, limit: usize },
+}
+
+impl fmt::Display for ClusteringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusteringError::DimensionMismatch {
+                what,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{what} has {actual} values where {expected} were expected"
+            ),
+            ClusteringError::TooFewObservations { axis, count } => write!(
+                f,
+                "correlation between {axis} needs profiles of at least 2 \
+                 values, got {count}"
+            ),
+            ClusteringError::NonFiniteInput { row, col, value } => write!(
+                f,
+                "value {value} at row {row}, column {col} is not finite"
+            ),
+            ClusteringError::UnsupportedOption(message) => f.write_str(message),
+            ClusteringError::TooManyLeaves { count, limit } => write!(
+                f,
+                "optimal leaf ordering handles at most {limit} leaves, got \
+                 {count}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClusteringError {}
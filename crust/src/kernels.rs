@@ -0,0 +1,149 @@
+//! Inner loops of the distance metrics. With the `simd` feature and a
+//! wasm32 build that enables the `simd128` target feature, they process two
+//! values per instruction; everywhere else the scalar loops are used. The
+//! two sum in a different order, so results may differ in the last bits.
+
+#[cfg(all(
+    feature = "simd",
+    target_arch = "wasm32",
+    target_feature = "simd128"
+))]
+use simd128 as kernels;
+
+#[cfg(not(all(
+    feature = "simd",
+    target_arch = "wasm32",
+    target_feature = "simd128"
+)))]
+use scalar as kernels;
+
+/// Sum of `(x - y)²` over the pairs of values of `left` and `right`.
+pub fn squared_euclidean(left: &[f64], right: &[f64]) -> f64 {
+    kernels::squared_euclidean(left, right)
+}
+
+/// Sum of `|x - y|` over the pairs of values of `left` and `right`.
+pub fn manhattan(left: &[f64], right: &[f64]) -> f64 {
+    kernels::manhattan(left, right)
+}
+
+/// Sums of `x * y`, `x²` and `y²` over the pairs of values of `left` and
+/// `right`, in a single pass.
+pub fn dot_and_norms(left: &[f64], right: &[f64]) -> (f64, f64, f64) {
+    kernels::dot_and_norms(left, right)
+}
+
+pub(crate) mod scalar {
+    pub fn squared_euclidean(left: &[f64], right: &[f64]) -> f64 {
+        left.iter()
+            .zip(right.iter())
+            .fold(0.0, |sum, (x, y)| sum + (x - y).powi(2))
+    }
+
+    pub fn manhattan(left: &[f64], right: &[f64]) -> f64 {
+        left.iter()
+            .zip(right.iter())
+            .fold(0.0, |sum, (x, y)| sum + (x - y).abs())
+    }
+
+    pub fn dot_and_norms(left: &[f64], right: &[f64]) -> (f64, f64, f64) {
+        left.iter().zip(right.iter()).fold(
+            (0.0, 0.0, 0.0),
+            |(dot, left_norm, right_norm), (x, y)| {
+                (dot + x * y, left_norm + x * x, right_norm + y * y)
+            },
+        )
+    }
+}
+
+#[cfg(all(
+    feature = "simd",
+    target_arch = "wasm32",
+    target_feature = "simd128"
+))]
+mod simd128 {
+    use core::arch::wasm32::{
+        f64x2_abs, f64x2_add, f64x2_extract_lane, f64x2_mul, f64x2_splat,
+        f64x2_sub, v128, v128_load,
+    };
+
+    use super::scalar;
+
+    /// The two values of a chunk of `chunks_exact(2)` as one vector.
+    fn load(pair: &[f64]) -> v128 {
+        debug_assert_eq!(pair.len(), 2);
+        // SAFETY: the pair spans the 16 bytes of a v128, and wasm loads
+        // need no alignment
+        unsafe { v128_load(pair.as_ptr().cast()) }
+    }
+
+    fn sum_lanes(sums: v128) -> f64 {
+        f64x2_extract_lane::<0>(sums) + f64x2_extract_lane::<1>(sums)
+    }
+
+    /// Sums `lanes(x, y)` over the pairs of `left` and `right` two at a
+    /// time, and leaves a last odd pair to the `scalar` kernel.
+    fn fold_pairs(
+        left: &[f64],
+        right: &[f64],
+        lanes: impl Fn(v128, v128) -> v128,
+        scalar: impl Fn(&[f64], &[f64]) -> f64,
+    ) -> f64 {
+        let length = left.len().min(right.len());
+        let left = left[..length].chunks_exact(2);
+        let right = right[..length].chunks_exact(2);
+        let tail = scalar(left.remainder(), right.remainder());
+
+        let sums = left.zip(right).fold(f64x2_splat(0.0), |sums, (x, y)| {
+            f64x2_add(sums, lanes(load(x), load(y)))
+        });
+        sum_lanes(sums) + tail
+    }
+
+    pub fn squared_euclidean(left: &[f64], right: &[f64]) -> f64 {
+        fold_pairs(
+            left,
+            right,
+            |x, y| {
+                let difference = f64x2_sub(x, y);
+                f64x2_mul(difference, difference)
+            },
+            scalar::squared_euclidean,
+        )
+    }
+
+    pub fn manhattan(left: &[f64], right: &[f64]) -> f64 {
+        fold_pairs(
+            left,
+            right,
+            |x, y| f64x2_abs(f64x2_sub(x, y)),
+            scalar::manhattan,
+        )
+    }
+
+    pub fn dot_and_norms(left: &[f64], right: &[f64]) -> (f64, f64, f64) {
+        let length = left.len().min(right.len());
+        let left = left[..length].chunks_exact(2);
+        let right = right[..length].chunks_exact(2);
+        let (dot, left_norm, right_norm) =
+            scalar::dot_and_norms(left.remainder(), right.remainder());
+
+        let zero = f64x2_splat(0.0);
+        let (dots, left_norms, right_norms) = left.zip(right).fold(
+            (zero, zero, zero),
+            |(dots, left_norms, right_norms), (x, y)| {
+                let (x, y) = (load(x), load(y));
+                (
+                    f64x2_add(dots, f64x2_mul(x, y)),
+                    f64x2_add(left_norms, f64x2_mul(x, x)),
+                    f64x2_add(right_norms, f64x2_mul(y, y)),
+                )
+            },
+        );
+        (
+            sum_lanes(dots) + dot,
+            sum_lanes(left_norms) + left_norm,
+            sum_lanes(right_norms) + right_norm,
+        )
+    }
+}
@@ -1,15 +1,59 @@
 use js_sys::{Float64Array, Uint32Array};
-use utils::{MatrixLike, MatrixView};
+use utils::{CondensedMatrix, MatrixLike, MatrixView};
 use wasm_bindgen::prelude::*;
 
 mod distance;
+mod dynamic_cut;
+mod engine;
+mod error;
+mod kernels;
 mod linkage;
+mod normalization;
+mod ordering;
+mod parallel;
+mod scaling;
 mod tree;
 mod utils;
 
-use crate::distance::DistanceMetric;
+use crate::distance::{
+    Distance, DistanceKind, DistanceMetric, NanPolicy, Pearson,
+};
+use crate::dynamic_cut::{
+    DynamicCutMethod, DynamicCutOptions, cutree_hybrid, cutree_tree,
+};
+use crate::engine::{
+    Merge, lance_williams_linkage, mst_single_linkage, nn_chain_linkage,
+};
+use crate::error::ClusteringError;
 use crate::linkage::LinkageFunction;
-use crate::tree::{HcTree, Node};
+use crate::normalization::{Normalization, normalize};
+use crate::ordering::{
+    LeafOrdering, MAX_OPTIMAL_LEAVES, optimal_leaf_ordering,
+};
+use crate::parallel::{in_pool, items};
+use crate::scaling::{Scaling, log2_transform, scale};
+use crate::tree::{DendrogramOrientation, HcTree};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Starts the Web Workers that run the `parallel` feature's threads, to be
+/// awaited once after the module is initialised, e.g. with
+/// `navigator.hardwareConcurrency` threads. Needs a cross-origin isolated
+/// page.
+#[cfg(all(feature = "parallel", target_arch = "wasm32"))]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+/// Runs the `parallel` feature's work on the calling thread alone, for
+/// pages that are not cross-origin isolated and so cannot start the workers
+/// of `init_thread_pool`. Called once in its place.
+#[cfg(all(feature = "parallel", target_arch = "wasm32"))]
+#[wasm_bindgen(js_name = initSingleThread)]
+pub fn init_single_thread() -> Result<(), JsError> {
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .use_current_thread()
+        .build_global()?)
+}
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy)]
@@ -19,252 +63,3188 @@ pub enum ClusteringAxis {
     Both,
 }
 
-pub fn find_nodes_to_merge_with_view(
-    nodes: &mut Vec<Node>,
-    distance_matrix: &MatrixView,
+/// Layout of a matrix given to `distance_matrix_clustering`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub enum DistanceMatrixLayout {
+    /// Upper triangle row by row without the diagonal, like SciPy's
+    /// `pdist` output.
+    Condensed,
+    /// All entries row by row, symmetric with a zero diagonal.
+    Square,
+}
+
+/// Optional settings of `hierarchical_clustering`. The defaults reproduce
+/// the plain clustering: no Minkowski exponent and ladderized leaves.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct ClusteringOptions {
+    /// Exponent of the Minkowski distance, required by that metric only.
+    pub minkowski_p: Option<f64>,
+    pub ordering: LeafOrdering,
+    /// One weight per row, required by `LeafOrdering::Weights` when rows
+    /// are clustered.
+    #[wasm_bindgen(getter_with_clone)]
+    pub row_weights: Option<Vec<f64>>,
+    /// One weight per column, see `row_weights`.
+    #[wasm_bindgen(getter_with_clone)]
+    pub col_weights: Option<Vec<f64>>,
+    /// Detects co-expression modules on every clustered axis, see
+    /// `row_modules`.
+    pub dynamic_cut: Option<DynamicCutOptions>,
+    /// Reports how well every dendrogram fits its distances, see
+    /// `row_cophenetic_correlation`.
+    pub cophenetic_correlation: bool,
+    /// Treatment of missing values, rows or columns that still cannot be
+    /// compared with the others are reported by `unplaced_rows` and
+    /// `unplaced_cols`.
+    pub nan_policy: NanPolicy,
+    /// Normalisation of the samples in the columns, applied first.
+    pub normalization: Normalization,
+    /// Takes log2(x + 1) of the values before `scaling` rescales them.
+    pub log_transform: bool,
+    /// Rescaling of the values, which are returned rescaled as well.
+    pub scaling: Scaling,
+}
+
+#[wasm_bindgen]
+impl ClusteringOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        ClusteringOptions::default()
+    }
+}
+
+impl ClusteringOptions {
+    /// Rejects options that cannot apply to an `nrows` × `ncols` matrix
+    /// clustered along `axis`. The Minkowski exponent is checked by
+    /// `DistanceMetric::from_kind`.
+    fn check(
+        &self,
+        nrows: usize,
+        ncols: usize,
+        axis: ClusteringAxis,
+    ) -> Result<(), ClusteringError> {
+        let clustered_weights = [
+            (
+                "row weights",
+                &self.row_weights,
+                nrows,
+                !matches!(axis, ClusteringAxis::Column),
+            ),
+            (
+                "column weights",
+                &self.col_weights,
+                ncols,
+                !matches!(axis, ClusteringAxis::Row),
+            ),
+        ];
+
+        for (what, weights, expected, clustered) in clustered_weights {
+            match weights {
+                Some(weights) if weights.len() != expected => {
+                    return Err(ClusteringError::DimensionMismatch {
+                        what,
+                        expected,
+                        actual: weights.len(),
+                    });
+                }
+                None if clustered && self.ordering == LeafOrdering::Weights => {
+                    return Err(ClusteringError::UnsupportedOption(format!(
+                        "LeafOrdering::Weights requires {what}"
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(dynamic_cut) = self.dynamic_cut
+            && dynamic_cut.deep_split > 4
+        {
+            return Err(ClusteringError::UnsupportedOption(format!(
+                "deep_split must be between 0 and 4, got {}",
+                dynamic_cut.deep_split
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+pub struct HierarchicalClusteringResult {
+    row_order: Vec<usize>,
+    col_order: Vec<usize>,
+    values: Vec<f64>,
+    // trees in the order they are displayed, `None` for unclustered axes
+    row_tree: Option<AxisTree>,
+    col_tree: Option<AxisTree>,
+    // dynamic tree cut labels, empty unless requested
+    row_modules: Vec<usize>,
+    col_modules: Vec<usize>,
+    row_cophenetic_correlation: Option<f64>,
+    col_cophenetic_correlation: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl HierarchicalClusteringResult {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        row_order: Vec<usize>,
+        col_order: Vec<usize>,
+        values: Vec<f64>,
+        row_linkage: Vec<f64>,
+        col_linkage: Vec<f64>,
+    ) -> Result<HierarchicalClusteringResult, JsError> {
+        let expected = row_order.len() * col_order.len();
+        if values.len() != expected {
+            return Err(ClusteringError::DimensionMismatch {
+                what: "values",
+                expected,
+                actual: values.len(),
+            }
+            .into());
+        }
+
+        Ok(HierarchicalClusteringResult {
+            row_tree: tree_from_linkage(row_order.len(), &row_linkage)?
+                .map(AxisTree::complete),
+            col_tree: tree_from_linkage(col_order.len(), &col_linkage)?
+                .map(AxisTree::complete),
+            row_modules: Vec::new(),
+            col_modules: Vec::new(),
+            row_cophenetic_correlation: None,
+            col_cophenetic_correlation: None,
+            row_order,
+            col_order,
+            values,
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn row_order(&self) -> Uint32Array {
+        let converted: Vec<u32> =
+            self.row_order.iter().map(|&x| x as u32).collect();
+        Uint32Array::from(converted.as_slice())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn col_order(&self) -> Uint32Array {
+        let converted: Vec<u32> =
+            self.col_order.iter().map(|&x| x as u32).collect();
+        Uint32Array::from(converted.as_slice())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn values(&self) -> Float64Array {
+        Float64Array::from(self.values.as_slice())
+    }
+
+    /// Row dendrogram as a SciPy linkage matrix flattened row by row
+    /// (`[left, right, height, size]` per merge), empty when rows were not
+    /// clustered. Leaf `i` is the `i`-th placed row, the rows listed by
+    /// `unplaced_rows` are skipped.
+    #[wasm_bindgen(getter)]
+    pub fn row_linkage(&self) -> Float64Array {
+        let linkage = self
+            .row_tree
+            .as_ref()
+            .map_or_else(Vec::new, |axis| axis.tree.linkage_matrix());
+        Float64Array::from(linkage.as_slice())
+    }
+
+    /// Column dendrogram in the same layout as `row_linkage`.
+    #[wasm_bindgen(getter)]
+    pub fn col_linkage(&self) -> Float64Array {
+        let linkage = self
+            .col_tree
+            .as_ref()
+            .map_or_else(Vec::new, |axis| axis.tree.linkage_matrix());
+        Float64Array::from(linkage.as_slice())
+    }
+
+    /// Row dendrogram in Newick format, leaves named by `labels` given in
+    /// input row order (or by row index when empty). `undefined` when rows
+    /// were not clustered.
+    pub fn row_newick(
+        &self,
+        labels: Vec<String>,
+    ) -> Result<Option<String>, JsError> {
+        Ok(self
+            .row_tree
+            .as_ref()
+            .map(|axis| axis.to_newick(&labels))
+            .transpose()?)
+    }
+
+    /// Column dendrogram in Newick format, see `row_newick`.
+    pub fn col_newick(
+        &self,
+        labels: Vec<String>,
+    ) -> Result<Option<String>, JsError> {
+        Ok(self
+            .col_tree
+            .as_ref()
+            .map(|axis| axis.to_newick(&labels))
+            .transpose()?)
+    }
+
+    /// Rows left out of the row dendrogram because their distances to the
+    /// others are undefined under `ClusteringOptions::nan_policy`, in input
+    /// order. They follow the placed rows in `row_order`.
+    #[wasm_bindgen(getter)]
+    pub fn unplaced_rows(&self) -> Uint32Array {
+        let unplaced: Vec<u32> = self
+            .row_tree
+            .as_ref()
+            .map_or(&[][..], |axis| axis.unplaced.as_slice())
+            .iter()
+            .map(|&x| x as u32)
+            .collect();
+        Uint32Array::from(unplaced.as_slice())
+    }
+
+    /// Column counterpart of `unplaced_rows`.
+    #[wasm_bindgen(getter)]
+    pub fn unplaced_cols(&self) -> Uint32Array {
+        let unplaced: Vec<u32> = self
+            .col_tree
+            .as_ref()
+            .map_or(&[][..], |axis| axis.unplaced.as_slice())
+            .iter()
+            .map(|&x| x as u32)
+            .collect();
+        Uint32Array::from(unplaced.as_slice())
+    }
+
+    /// Line segments drawing the row dendrogram, see
+    /// `HcTree::dendrogram_segments`. Empty when rows were not clustered.
+    pub fn row_dendrogram(
+        &self,
+        orientation: DendrogramOrientation,
+    ) -> Float64Array {
+        let segments = self.row_tree.as_ref().map_or_else(Vec::new, |axis| {
+            axis.tree.dendrogram_segments(orientation)
+        });
+        Float64Array::from(segments.as_slice())
+    }
+
+    /// Line segments drawing the column dendrogram, see `row_dendrogram`.
+    pub fn col_dendrogram(
+        &self,
+        orientation: DendrogramOrientation,
+    ) -> Float64Array {
+        let segments = self.col_tree.as_ref().map_or_else(Vec::new, |axis| {
+            axis.tree.dendrogram_segments(orientation)
+        });
+        Float64Array::from(segments.as_slice())
+    }
+
+    /// Flat cluster label of every row, in input row order, after cutting
+    /// the row dendrogram into `k` clusters, see `HcTree::cut_by_count`.
+    /// Unplaced rows get 0. Empty when rows were not clustered.
+    pub fn cut_rows_by_count(&self, k: usize) -> Uint32Array {
+        cluster_labels(self.row_tree.as_ref(), |tree| tree.cut_by_count(k))
+    }
+
+    /// Flat cluster label of every row after cutting the row dendrogram at
+    /// `height`, see `HcTree::cut_by_height`.
+    pub fn cut_rows_by_height(&self, height: f64) -> Uint32Array {
+        cluster_labels(self.row_tree.as_ref(), |tree| {
+            tree.cut_by_height(height)
+        })
+    }
+
+    /// Module label of every row in input row order from the dynamic tree
+    /// cut requested through `ClusteringOptions::dynamic_cut`: 1 for the
+    /// largest module, 0 for rows outside of every module. Empty when not
+    /// requested or rows were not clustered.
+    #[wasm_bindgen(getter)]
+    pub fn row_modules(&self) -> Uint32Array {
+        let labels: Vec<u32> =
+            self.row_modules.iter().map(|&x| x as u32).collect();
+        Uint32Array::from(labels.as_slice())
+    }
+
+    /// Column counterpart of `row_modules`.
+    #[wasm_bindgen(getter)]
+    pub fn col_modules(&self) -> Uint32Array {
+        let labels: Vec<u32> =
+            self.col_modules.iter().map(|&x| x as u32).collect();
+        Uint32Array::from(labels.as_slice())
+    }
+
+    /// Cophenetic correlation of the row dendrogram, see
+    /// `cophenetic_correlation`. `undefined` unless requested through
+    /// `ClusteringOptions::cophenetic_correlation` and rows were clustered.
+    #[wasm_bindgen(getter)]
+    pub fn row_cophenetic_correlation(&self) -> Option<f64> {
+        self.row_cophenetic_correlation
+    }
+
+    /// Column counterpart of `row_cophenetic_correlation`.
+    #[wasm_bindgen(getter)]
+    pub fn col_cophenetic_correlation(&self) -> Option<f64> {
+        self.col_cophenetic_correlation
+    }
+
+    /// Column counterpart of `cut_rows_by_count`.
+    pub fn cut_cols_by_count(&self, k: usize) -> Uint32Array {
+        cluster_labels(self.col_tree.as_ref(), |tree| tree.cut_by_count(k))
+    }
+
+    /// Column counterpart of `cut_rows_by_height`.
+    pub fn cut_cols_by_height(&self, height: f64) -> Uint32Array {
+        cluster_labels(self.col_tree.as_ref(), |tree| {
+            tree.cut_by_height(height)
+        })
+    }
+}
+
+fn cluster_labels(
+    tree: Option<&AxisTree>,
+    cut: impl Fn(&HcTree) -> Vec<usize>,
+) -> Uint32Array {
+    let labels: Vec<u32> = tree
+        .map_or_else(Vec::new, |axis| axis.expand(&cut(&axis.tree)))
+        .into_iter()
+        .map(|label| label as u32)
+        .collect();
+    Uint32Array::from(labels.as_slice())
+}
+
+/// Dendrogram of one axis together with the rows it could place.
+struct AxisTree {
+    tree: HcTree,
+    /// Input index of every leaf of `tree`.
+    placed: Vec<usize>,
+    /// Input indices left out of `tree`, ascending.
+    unplaced: Vec<usize>,
+}
+
+impl AxisTree {
+    /// A tree that places every row, its leaf ids are the input indices.
+    fn complete(tree: HcTree) -> Self {
+        AxisTree {
+            placed: (0..tree.preorder_leaf_traversal().count()).collect(),
+            unplaced: Vec::new(),
+            tree,
+        }
+    }
+
+    /// Display order: the placed rows as in the tree, then the others.
+    fn order(&self) -> Vec<usize> {
+        leaf_order(&self.tree)
+            .into_iter()
+            .map(|leaf| self.placed[leaf])
+            .chain(self.unplaced.iter().copied())
+            .collect()
+    }
+
+    /// Spreads labels given per leaf over all input rows, unplaced rows
+    /// get 0.
+    fn expand(&self, leaf_labels: &[usize]) -> Vec<usize> {
+        let mut labels = vec![0; self.placed.len() + self.unplaced.len()];
+        for (&row, &label) in self.placed.iter().zip(leaf_labels) {
+            labels[row] = label;
+        }
+        labels
+    }
+
+    fn to_newick(&self, labels: &[String]) -> Result<String, ClusteringError> {
+        let number_of_observations = self.placed.len() + self.unplaced.len();
+        let leaf_labels: Vec<String> = if labels.is_empty() {
+            self.placed.iter().map(|row| row.to_string()).collect()
+        } else if labels.len() == number_of_observations {
+            self.placed.iter().map(|&row| labels[row].clone()).collect()
+        } else {
+            return Err(ClusteringError::DimensionMismatch {
+                what: "labels",
+                expected: number_of_observations,
+                actual: labels.len(),
+            });
+        };
+        Ok(self.tree.to_newick(&leaf_labels))
+    }
+}
+
+/// Rebuilds a ladderized tree from a linkage matrix, `None` when the matrix
+/// is empty. Otherwise it must join all `number_of_leaves` leaves, every
+/// row merging two nodes that exist and have not been merged yet.
+fn tree_from_linkage(
+    number_of_leaves: usize,
+    linkage: &[f64],
+) -> Result<Option<HcTree>, ClusteringError> {
+    if linkage.is_empty() {
+        return Ok(None);
+    }
+
+    let expected = 4 * number_of_leaves.saturating_sub(1);
+    if linkage.len() != expected {
+        return Err(ClusteringError::DimensionMismatch {
+            what: "linkage matrix",
+            expected,
+            actual: linkage.len(),
+        });
+    }
+
+    let mut merged: Vec<bool> = vec![false; 2 * number_of_leaves - 1];
+    for (k, row) in linkage.chunks_exact(4).enumerate() {
+        let joins_free_nodes = row[0] != row[1]
+            && row[..2].iter().all(|&id| {
+                id.fract() == 0.0
+                    && id >= 0.0
+                    && id < (number_of_leaves + k) as f64
+                    && !merged[id as usize]
+            });
+        if !joins_free_nodes {
+            return Err(ClusteringError::UnsupportedOption(format!(
+                "linkage matrix row {k} does not join two unmerged nodes"
+            )));
+        }
+        merged[row[0] as usize] = true;
+        merged[row[1] as usize] = true;
+    }
+
+    let mut tree = HcTree::from_linkage_matrix(number_of_leaves, linkage);
+    tree.ladderize();
+
+    Ok(Some(tree))
+}
+
+pub fn compute_distance_matrix_from_view(
+    data_matrix: &MatrixView,
+    distance: DistanceMetric,
+) -> Result<Vec<f64>, ClusteringError> {
+    let rows: Vec<usize> = (0..data_matrix.nrows()).collect();
+    compute_distance_matrix_for_rows(data_matrix, &rows, distance)
+}
+
+/// Condensed distances between the `rows` of `data_matrix`, in that order.
+/// Missing values are skipped pair by pair, see
+/// `DistanceMetric::compute_pairwise_complete`.
+pub fn compute_distance_matrix_for_rows(
+    data_matrix: &MatrixView,
+    rows: &[usize],
+    distance: DistanceMetric,
+) -> Result<Vec<f64>, ClusteringError> {
+    let mut distance_matrix: Vec<f64> =
+        vec![0.0; rows.len() * rows.len().saturating_sub(1) / 2];
+
+    // gathered once, so that columns are not copied again for every pair
+    let profiles: Vec<_> = rows.iter().map(|&i| data_matrix.row(i)).collect();
+
+    // the distances of every row to the rows after it, one slice per row,
+    // filled independently and in parallel with the `parallel` feature
+    let mut segments: Vec<&mut [f64]> = Vec::with_capacity(rows.len());
+    let mut rest = distance_matrix.as_mut_slice();
+    for position in 0..rows.len() {
+        let (segment, tail) = rest.split_at_mut(rows.len() - position - 1);
+        segments.push(segment);
+        rest = tail;
+    }
+
+    items(segments)
+        .enumerate()
+        .try_for_each(|(position, segment)| {
+            for (value, row_j) in
+                segment.iter_mut().zip(&profiles[position + 1..])
+            {
+                *value = distance
+                    .compute_pairwise_complete(&profiles[position], row_j)?;
+            }
+            Ok(())
+        })?;
+
+    Ok(distance_matrix)
+}
+
+/// Distances between the rows of `data_matrix` that can be placed in a tree
+/// under `nan_policy`, with the input indices of those rows and of the
+/// others.
+///
+/// Rows whose distance to some other row is still undefined are dropped
+/// one at a time, always the one with the most undefined distances (the
+/// lowest index on ties), until every remaining distance is defined.
+pub fn placeable_distances(
+    data_matrix: &MatrixView,
+    distance: DistanceMetric,
+    nan_policy: NanPolicy,
+) -> Result<(CondensedMatrix, Vec<usize>, Vec<usize>), ClusteringError> {
+    let candidates: Vec<usize> = (0..data_matrix.nrows())
+        .filter(|&i| {
+            nan_policy != NanPolicy::DropIncomplete
+                || !data_matrix.row(i).iter().any(|value| value.is_nan())
+        })
+        .collect();
+    let distances = CondensedMatrix::new(
+        compute_distance_matrix_for_rows(data_matrix, &candidates, distance)?,
+        candidates.len(),
+    );
+
+    Ok(place_observations(
+        &distances,
+        &candidates,
+        data_matrix.nrows(),
+    ))
+}
+
+/// Reduces the `distances` between the `candidates`, given as input
+/// indices out of `number_of_observations`, to the observations whose
+/// distances are all defined. Returns them with the input indices of the
+/// observations kept and of all the others, see `placeable_distances`.
+fn place_observations(
+    distances: &CondensedMatrix,
+    candidates: &[usize],
+    number_of_observations: usize,
+) -> (CondensedMatrix, Vec<usize>, Vec<usize>) {
+    let size = distances.size();
+    let mut undefined: Vec<usize> = (0..size)
+        .map(|i| {
+            (0..size)
+                .filter(|&j| j != i && distances.get(i, j).is_nan())
+                .count()
+        })
+        .collect();
+    let mut kept: Vec<bool> = vec![true; size];
+
+    while let Some(worst) = (0..size)
+        .filter(|&i| kept[i] && undefined[i] > 0)
+        .max_by_key(|&i| (undefined[i], std::cmp::Reverse(i)))
+    {
+        kept[worst] = false;
+        for other in 0..size {
+            if kept[other]
+                && other != worst
+                && distances.get(worst, other).is_nan()
+            {
+                undefined[other] -= 1;
+            }
+        }
+    }
+
+    let positions: Vec<usize> = (0..size).filter(|&i| kept[i]).collect();
+    let placed: Vec<usize> = positions
+        .iter()
+        .map(|&position| candidates[position])
+        .collect();
+    let unplaced: Vec<usize> = (0..number_of_observations)
+        .filter(|row| placed.binary_search(row).is_err())
+        .collect();
+
+    (distances.select(&positions), placed, unplaced)
+}
+
+/// Clusters the rows of `data_matrix` and arranges the children of every
+/// merge according to `ordering`. `weights` holds one value per row and is
+/// only read by `LeafOrdering::Weights`.
+pub fn build_tree_with_views(
+    data_matrix: &MatrixView,
+    distance: DistanceMetric,
+    linkage: LinkageFunction,
+    ordering: LeafOrdering,
+    weights: Option<&[f64]>,
+) -> Result<HcTree, ClusteringError> {
+    let distance_matrix = CondensedMatrix::new(
+        compute_distance_matrix_from_view(data_matrix, distance)?,
+        data_matrix.nrows(),
+    );
+    let means: Vec<f64> = if ordering == LeafOrdering::MeanValue {
+        (0..data_matrix.nrows())
+            .map(|i| observed_mean(&data_matrix.row(i)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    build_tree(
+        distance_matrix,
+        linkage,
+        ordering,
+        if ordering == LeafOrdering::MeanValue {
+            Some(&means)
+        } else {
+            weights
+        },
+    )
+}
+
+/// Clusters the observations of `distance_matrix` and arranges the children
+/// of every merge according to `ordering`. `weights` holds one value per
+/// observation and is read by `LeafOrdering::MeanValue` and
+/// `LeafOrdering::Weights`.
+pub fn build_tree(
+    distance_matrix: CondensedMatrix,
+    linkage: LinkageFunction,
+    ordering: LeafOrdering,
+    weights: Option<&[f64]>,
+) -> Result<HcTree, ClusteringError> {
+    let number_of_observations = distance_matrix.size();
+    if ordering == LeafOrdering::Optimal
+        && number_of_observations > MAX_OPTIMAL_LEAVES
+    {
+        return Err(ClusteringError::TooManyLeaves {
+            count: number_of_observations,
+            limit: MAX_OPTIMAL_LEAVES,
+        });
+    }
+
+    // the linkage step consumes its matrix, the optimal ordering needs the
+    // original distances
+    let original_distances =
+        (ordering == LeafOrdering::Optimal).then(|| distance_matrix.clone());
+
+    let merges = compute_merges(distance_matrix, linkage);
+    let mut tree = HcTree::from_merges(number_of_observations, &merges);
+
+    match ordering {
+        LeafOrdering::LadderizeDescending => tree.ladderize(),
+        LeafOrdering::LadderizeAscending => tree.ladderize_ascending(),
+        LeafOrdering::MergeOrder => {}
+        LeafOrdering::MeanValue | LeafOrdering::Weights => {
+            let weights = weights.ok_or_else(|| {
+                ClusteringError::UnsupportedOption(format!(
+                    "LeafOrdering::{ordering:?} requires weights"
+                ))
+            })?;
+            if weights.len() != number_of_observations {
+                return Err(ClusteringError::DimensionMismatch {
+                    what: "weights",
+                    expected: number_of_observations,
+                    actual: weights.len(),
+                });
+            }
+            tree.reorder_by_weights(weights);
+        }
+        LeafOrdering::Optimal => {
+            if let Some(original_distances) = original_distances {
+                optimal_leaf_ordering(&mut tree, &original_distances);
+            }
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Mean of the values that are not NaN, NaN when there are none.
+fn observed_mean(values: &[f64]) -> f64 {
+    let (sum, count) = values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    sum / count as f64
+}
+
+/// Pearson correlation between the cophenetic distances of `tree` and the
+/// `distances` it was built from: how faithfully the dendrogram represents
+/// them, 1 being a perfect fit. NaN for fewer than three leaves.
+pub fn cophenetic_correlation(
+    tree: &HcTree,
+    distances: &CondensedMatrix,
+) -> f64 {
+    1.0 - Pearson
+        .compute(tree.cophenetic_distances().as_slice(), distances.as_slice())
+}
+
+/// Picks the cheapest algorithm that is exact for `linkage`.
+///
+/// Equal distances merge the pair with the lowest node ids among the
+/// closest ones first, as the full pair rescan that clustered every linkage
+/// before did. The nearest-neighbour chain resolves them differently, so it
+/// gives way to the Lance–Williams engine on a copy of the distances as
+/// soon as it meets a tie (see `lance_williams_matches_previous_orders_test`).
+/// Single linkage, which the rescan did not offer, keeps the first of
+/// equally close candidates in index order as SciPy does.
+pub fn compute_merges(
+    distance_matrix: CondensedMatrix,
+    linkage: LinkageFunction,
+) -> Vec<Merge> {
+    // every merge starts a few parallel scans, cheaper from a pool thread
+    in_pool(|| match linkage {
+        LinkageFunction::Single => mst_single_linkage(&distance_matrix),
+        _ if linkage.is_reducible() => {
+            nn_chain_linkage(distance_matrix.clone(), linkage).unwrap_or_else(
+                || lance_williams_linkage(distance_matrix, linkage),
+            )
+        }
+        _ => lance_williams_linkage(distance_matrix, linkage),
+    })
+}
+
+pub fn cluster_with_views(
+    data_matrix: &MatrixView,
+    distance: DistanceMetric,
+    linkage: LinkageFunction,
+) -> Result<Vec<usize>, ClusteringError> {
+    Ok(leaf_order(&build_tree_with_views(
+        data_matrix,
+        distance,
+        linkage,
+        LeafOrdering::LadderizeDescending,
+        None,
+    )?))
+}
+
+fn leaf_order(tree: &HcTree) -> Vec<usize> {
+    tree.preorder_leaf_traversal().map(|node| node.id).collect()
+}
+
+#[wasm_bindgen]
+pub fn hierarchical_clustering(
+    nrows: usize,
+    ncols: usize,
+    values: Vec<f64>,
+    axis: ClusteringAxis,
+    linkage: LinkageFunction,
+    distance: DistanceKind,
+    options: &ClusteringOptions,
+) -> Result<HierarchicalClusteringResult, JsError> {
+    Ok(cluster_matrix(
+        nrows, ncols, values, axis, linkage, distance, options,
+    )?)
+}
+
+/// `hierarchical_clustering` with its error kept as a `ClusteringError`.
+pub fn cluster_matrix(
+    nrows: usize,
+    ncols: usize,
+    values: Vec<f64>,
+    axis: ClusteringAxis,
+    linkage: LinkageFunction,
+    distance: DistanceKind,
+    options: &ClusteringOptions,
+) -> Result<HierarchicalClusteringResult, ClusteringError> {
+    let distance = DistanceMetric::from_kind(distance, options.minkowski_p)?;
+    check_input(nrows, ncols, &values, axis, distance)?;
+    options.check(nrows, ncols, axis)?;
+
+    // clustered and displayed alike, so that the heatmap shows what the
+    // dendrograms were built from
+    let values = if options.normalization == Normalization::None {
+        values
+    } else {
+        normalize(
+            &MatrixView::new(&values, nrows, ncols),
+            options.normalization,
+        )?
+    };
+    let values = if options.log_transform {
+        log2_transform(&MatrixView::new(&values, nrows, ncols))?
+    } else {
+        values
+    };
+    let values = if options.scaling == Scaling::None {
+        values
+    } else {
+        scale(&MatrixView::new(&values, nrows, ncols), options.scaling)
+    };
+    let data_matrix = MatrixView::new(&values, nrows, ncols);
+
+    // the displayed values keep their gaps, only clustering sees the imputed
+    let imputed: Vec<f64> = if options.nan_policy == NanPolicy::ImputeRowMean {
+        impute_row_means(&data_matrix)
+    } else {
+        Vec::new()
+    };
+    let clustering_matrix = if imputed.is_empty() {
+        MatrixView::new(&values, nrows, ncols)
+    } else {
+        MatrixView::new(&imputed, nrows, ncols)
+    };
+
+    let row_tree = match axis {
+        ClusteringAxis::Row | ClusteringAxis::Both => Some(cluster_axis(
+            &clustering_matrix,
+            distance,
+            linkage,
+            options,
+            options.row_weights.as_deref(),
+        )?),
+        ClusteringAxis::Column => None,
+    };
+
+    let col_tree = match axis {
+        ClusteringAxis::Column | ClusteringAxis::Both => Some(cluster_axis(
+            &clustering_matrix.transposed(),
+            distance,
+            linkage,
+            options,
+            options.col_weights.as_deref(),
+        )?),
+        ClusteringAxis::Row => None,
+    };
+
+    let (row_modules, row_cophenetic_correlation) =
+        summarize_tree(row_tree.as_ref(), options, |rows| {
+            Ok(CondensedMatrix::new(
+                compute_distance_matrix_for_rows(
+                    &clustering_matrix,
+                    rows,
+                    distance,
+                )?,
+                rows.len(),
+            ))
+        })?;
+    let (col_modules, col_cophenetic_correlation) =
+        summarize_tree(col_tree.as_ref(), options, |cols| {
+            Ok(CondensedMatrix::new(
+                compute_distance_matrix_for_rows(
+                    &clustering_matrix.transposed(),
+                    cols,
+                    distance,
+                )?,
+                cols.len(),
+            ))
+        })?;
+
+    let row_order: Vec<usize> = row_tree
+        .as_ref()
+        .map_or_else(|| (0..nrows).collect(), AxisTree::order);
+    let col_order: Vec<usize> = col_tree
+        .as_ref()
+        .map_or_else(|| (0..ncols).collect(), AxisTree::order);
+
+    Ok(HierarchicalClusteringResult {
+        values: {
+            data_matrix
+                .permutation(row_order.as_slice(), col_order.as_slice())
+                .to_row_major()
+        },
+        row_order,
+        col_order,
+        row_tree,
+        col_tree,
+        row_modules,
+        col_modules,
+        row_cophenetic_correlation,
+        col_cophenetic_correlation,
+    })
+}
+
+/// Rejects values that do not fill an `nrows` × `ncols` matrix, profiles
+/// too short to correlate and infinite values. Axes of any size can be
+/// clustered, with fewer than two rows or columns their order stays as is.
+fn check_input(
+    nrows: usize,
+    ncols: usize,
+    values: &[f64],
+    axis: ClusteringAxis,
+    distance: DistanceMetric,
+) -> Result<(), ClusteringError> {
+    if values.len() != nrows * ncols {
+        return Err(ClusteringError::DimensionMismatch {
+            what: "values",
+            expected: nrows * ncols,
+            actual: values.len(),
+        });
+    }
+
+    // a single row or column has nothing to be compared with
+    let correlates =
+        matches!(distance, DistanceMetric::Pearson | DistanceMetric::Spearman);
+    let clusters_rows = !matches!(axis, ClusteringAxis::Column) && nrows > 1;
+    let clusters_cols = !matches!(axis, ClusteringAxis::Row) && ncols > 1;
+    if correlates && clusters_rows && ncols < 2 {
+        return Err(ClusteringError::TooFewObservations {
+            axis: "rows",
+            count: ncols,
+        });
+    }
+    if correlates && clusters_cols && nrows < 2 {
+        return Err(ClusteringError::TooFewObservations {
+            axis: "columns",
+            count: nrows,
+        });
+    }
+
+    match values.iter().position(|value| value.is_infinite()) {
+        Some(index) => Err(ClusteringError::NonFiniteInput {
+            row: index / ncols,
+            col: index % ncols,
+            value: values[index],
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Copy of the matrix values with every NaN replaced by the mean of the
+/// observed values of its row. Rows without any observed value stay NaN.
+fn impute_row_means(data_matrix: &MatrixView) -> Vec<f64> {
+    let mut imputed: Vec<f64> =
+        Vec::with_capacity(data_matrix.nrows() * data_matrix.ncols());
+    for i in 0..data_matrix.nrows() {
+        let row = data_matrix.row(i);
+        let mean = observed_mean(&row);
+        imputed.extend(
+            row.iter()
+                .map(|&value| if value.is_nan() { mean } else { value }),
+        );
+    }
+    imputed
+}
+
+/// Clusters `size` observations by distances computed elsewhere, such as
+/// genotype distances or distances derived from sequence similarity.
+///
+/// Only rows are clustered: the values of the result are the square
+/// distance matrix with both rows and columns in `row_order`, and
+/// `col_order` repeats `row_order`. NaN distances leave observations
+/// unplaced as described for `placeable_distances`, under
+/// `NanPolicy::DropIncomplete` every observation with one is left out.
+/// `LeafOrdering::MeanValue`, `NanPolicy::ImputeRowMean`, normalisation,
+/// scaling and column weights need the values behind the distances and are
+/// rejected.
+#[wasm_bindgen]
+pub fn distance_matrix_clustering(
+    size: usize,
+    distances: Vec<f64>,
+    layout: DistanceMatrixLayout,
+    linkage: LinkageFunction,
+    options: &ClusteringOptions,
+) -> Result<HierarchicalClusteringResult, JsError> {
+    Ok(cluster_distance_matrix(
+        size, &distances, layout, linkage, options,
+    )?)
+}
+
+/// `distance_matrix_clustering` with its error kept as a `ClusteringError`.
+pub fn cluster_distance_matrix(
+    size: usize,
+    distances: &[f64],
+    layout: DistanceMatrixLayout,
+    linkage: LinkageFunction,
+    options: &ClusteringOptions,
+) -> Result<HierarchicalClusteringResult, ClusteringError> {
+    let distances = condensed_distances(size, distances, layout)?;
+    if options.col_weights.is_some() {
+        return Err(ClusteringError::UnsupportedOption(
+            "column weights need values, not distances".into(),
+        ));
+    }
+    options.check(size, 0, ClusteringAxis::Row)?;
+    if options.ordering == LeafOrdering::MeanValue {
+        return Err(ClusteringError::UnsupportedOption(
+            "LeafOrdering::MeanValue needs values, not distances".into(),
+        ));
+    }
+    if options.nan_policy == NanPolicy::ImputeRowMean {
+        return Err(ClusteringError::UnsupportedOption(
+            "NanPolicy::ImputeRowMean needs values, not distances".into(),
+        ));
+    }
+    if options.normalization != Normalization::None
+        || options.log_transform
+        || options.scaling != Scaling::None
+    {
+        return Err(ClusteringError::UnsupportedOption(
+            "normalisation and scaling apply to values, not distances".into(),
+        ));
+    }
+
+    let candidates: Vec<usize> = (0..size)
+        .filter(|&i| {
+            options.nan_policy != NanPolicy::DropIncomplete
+                || (0..size).all(|j| j == i || !distances.get(i, j).is_nan())
+        })
+        .collect();
+    let (distance_matrix, placed, unplaced) =
+        place_observations(&distances.select(&candidates), &candidates, size);
+
+    let row_tree = axis_tree(
+        distance_matrix,
+        placed,
+        unplaced,
+        linkage,
+        options.ordering,
+        options.row_weights.as_deref(),
+    )?;
+    let (row_modules, row_cophenetic_correlation) =
+        summarize_tree(Some(&row_tree), options, |rows| {
+            Ok(distances.select(rows))
+        })?;
+    let row_order = row_tree.order();
+    let distances = &distances;
+
+    Ok(HierarchicalClusteringResult {
+        values: row_order
+            .iter()
+            .flat_map(|&i| {
+                row_order.iter().map(move |&j| {
+                    if i == j { 0.0 } else { distances.get(i, j) }
+                })
+            })
+            .collect(),
+        col_order: row_order.clone(),
+        row_order,
+        row_tree: Some(row_tree),
+        col_tree: None,
+        row_modules,
+        col_modules: Vec::new(),
+        row_cophenetic_correlation,
+        col_cophenetic_correlation: None,
+    })
+}
+
+/// Reads a distance matrix of `size` observations in `layout`, rejecting
+/// anything that is not one: a wrong length, an asymmetric square matrix or
+/// one with a non-zero diagonal, negative or infinite distances.
+fn condensed_distances(
+    size: usize,
+    distances: &[f64],
+    layout: DistanceMatrixLayout,
+) -> Result<CondensedMatrix, ClusteringError> {
+    let expected = match layout {
+        DistanceMatrixLayout::Condensed => size * size.saturating_sub(1) / 2,
+        DistanceMatrixLayout::Square => size * size,
+    };
+    if distances.len() != expected {
+        return Err(ClusteringError::DimensionMismatch {
+            what: "distance matrix",
+            expected,
+            actual: distances.len(),
+        });
+    }
+
+    let condensed = match layout {
+        DistanceMatrixLayout::Condensed => {
+            CondensedMatrix::new(distances.to_vec(), size)
+        }
+        DistanceMatrixLayout::Square => {
+            let square = MatrixView::new(distances, size, size);
+            let mut upper: Vec<f64> =
+                Vec::with_capacity(size * size.saturating_sub(1) / 2);
+
+            for i in 0..size {
+                if square.get(i, i) != 0.0 {
+                    return Err(ClusteringError::UnsupportedOption(format!(
+                        "distance matrix has {} on its diagonal at {i}",
+                        square.get(i, i)
+                    )));
+                }
+                for j in i + 1..size {
+                    let (value, mirrored) =
+                        (square.get(i, j), square.get(j, i));
+                    if value != mirrored
+                        && !(value.is_nan() && mirrored.is_nan())
+                    {
+                        return Err(ClusteringError::UnsupportedOption(
+                            format!(
+                                "distance matrix is not symmetric at row {i}, \
+                             column {j}"
+                            ),
+                        ));
+                    }
+                    upper.push(value);
+                }
+            }
+
+            CondensedMatrix::new(upper, size)
+        }
+    };
+
+    for i in 0..size {
+        for j in i + 1..size {
+            let value = condensed.get(i, j);
+            if value.is_infinite() {
+                return Err(ClusteringError::NonFiniteInput {
+                    row: i,
+                    col: j,
+                    value,
+                });
+            }
+            if value < 0.0 {
+                return Err(ClusteringError::UnsupportedOption(format!(
+                    "distance {value} between {i} and {j} is negative"
+                )));
+            }
+        }
+    }
+
+    Ok(condensed)
+}
+
+/// Clusters the rows of `data_matrix` that can be placed under
+/// `options.nan_policy`. `weights` holds one value per input row.
+fn cluster_axis(
     data_matrix: &MatrixView,
+    distance: DistanceMetric,
     linkage: LinkageFunction,
-) {
-    let mut pairs: Vec<LinkageResult> =
-        Vec::with_capacity(nodes.len() * (nodes.len() - 1) / 2);
+    options: &ClusteringOptions,
+    weights: Option<&[f64]>,
+) -> Result<AxisTree, ClusteringError> {
+    let (distance_matrix, placed, unplaced) =
+        placeable_distances(data_matrix, distance, options.nan_policy)?;
+
+    let means: Vec<f64> = if options.ordering == LeafOrdering::MeanValue {
+        (0..data_matrix.nrows())
+            .map(|i| observed_mean(&data_matrix.row(i)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let weights = if options.ordering == LeafOrdering::MeanValue {
+        Some(means.as_slice())
+    } else {
+        weights
+    };
+
+    axis_tree(
+        distance_matrix,
+        placed,
+        unplaced,
+        linkage,
+        options.ordering,
+        weights,
+    )
+}
+
+/// Clusters the placed observations of one axis, `distance_matrix` holding
+/// their distances and `weights` one value per input observation.
+fn axis_tree(
+    distance_matrix: CondensedMatrix,
+    placed: Vec<usize>,
+    unplaced: Vec<usize>,
+    linkage: LinkageFunction,
+    ordering: LeafOrdering,
+    weights: Option<&[f64]>,
+) -> Result<AxisTree, ClusteringError> {
+    let leaf_weights: Option<Vec<f64>> =
+        weights.map(|weights| placed.iter().map(|&i| weights[i]).collect());
+
+    Ok(AxisTree {
+        tree: build_tree(
+            distance_matrix,
+            linkage,
+            ordering,
+            leaf_weights.as_deref(),
+        )?,
+        placed,
+        unplaced,
+    })
+}
+
+/// Module labels and cophenetic correlation of one clustered axis, as far
+/// as `options` asks for them. Module labels cover all input rows.
+/// `distances` computes the distances between the given input rows when
+/// they are needed.
+fn summarize_tree(
+    axis: Option<&AxisTree>,
+    options: &ClusteringOptions,
+    distances: impl FnOnce(&[usize]) -> Result<CondensedMatrix, ClusteringError>,
+) -> Result<(Vec<usize>, Option<f64>), ClusteringError> {
+    let Some(axis) = axis else {
+        return Ok((Vec::new(), None));
+    };
+    let tree = &axis.tree;
+
+    // recomputed rather than kept around during clustering
+    let needs_distances = options.cophenetic_correlation
+        || options.dynamic_cut.is_some_and(|dynamic_cut| {
+            dynamic_cut.method == DynamicCutMethod::Hybrid
+        });
+    let distances = if needs_distances {
+        Some(distances(&axis.placed)?)
+    } else {
+        None
+    };
+    let modules = match options.dynamic_cut {
+        Some(dynamic_cut) => axis.expand(&match dynamic_cut.method {
+            DynamicCutMethod::Tree => cutree_tree(tree, &dynamic_cut),
+            DynamicCutMethod::Hybrid => cutree_hybrid(
+                tree,
+                distances.as_ref().expect("computed for the hybrid cut"),
+                &dynamic_cut,
+            ),
+        }),
+        None => Vec::new(),
+    };
+
+    let correlation = options.cophenetic_correlation.then(|| {
+        cophenetic_correlation(
+            tree,
+            distances.as_ref().expect("computed for the correlation"),
+        )
+    });
+
+    Ok((modules, correlation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalization::{median_of_ratios_size_factors, tmm_factors};
+    use crate::tree::Node;
+    use std::borrow::Cow;
+
+    // 10 × 3, with rows 3, 7, 8 and 9 constant and ties between distances
+    const TEN_ROWS: [f64; 30] = [
+        1.0, 2.0, 3.0, // row 0
+        2.0, 3.0, 4.0, // row 1
+        3.0, 4.0, 5.0, // row 2
+        8.0, 8.0, 8.0, // row 3
+        1.0, 0.0, 1.0, // row 4
+        0.0, 1.0, 0.0, // row 5
+        6.0, 5.0, 4.0, // row 6
+        9.0, 9.0, 9.0, // row 7
+        2.0, 2.0, 2.0, // row 8
+        5.0, 5.0, 5.0, // row 9
+    ];
+
+    // 8 × 3, without ties between distances
+    const EIGHT_ROWS: [f64; 24] = [
+        0.42, 1.87, 3.10, // row 0
+        2.95, 0.33, 1.48, // row 1
+        0.58, 1.61, 2.74, // row 2
+        4.12, 3.96, 0.21, // row 3
+        2.71, 0.92, 1.13, // row 4
+        3.88, 4.35, 0.67, // row 5
+        1.49, 2.26, 2.05, // row 6
+        5.03, 1.17, 3.62, // row 7
+    ];
+
+    #[test]
+    fn cluster_with_views_test() {
+        let data: Vec<f64> = TEN_ROWS.to_vec();
+
+        let result = cluster_matrix(
+            10,
+            3,
+            data,
+            super::ClusteringAxis::Row,
+            super::LinkageFunction::Average,
+            super::DistanceKind::Chebyshev,
+            &ClusteringOptions::new(),
+        )
+        .unwrap();
+
+        result
+            .row_order
+            .iter()
+            .enumerate()
+            .for_each(|(x, y)| println!("{x}, {y}"));
+    }
+
+    #[test]
+    fn lance_williams_matches_previous_orders_test() {
+        let data: Vec<f64> = TEN_ROWS.to_vec();
+
+        // orders produced by the full pair rescan for the same input; with
+        // Chebyshev 8 is as close to {0, 1, 2} as to {4, 5} and joins the
+        // latter, which the nearest-neighbour chain alone would not
+        let cases = [
+            (
+                LinkageFunction::Average,
+                DistanceKind::Chebyshev,
+                vec![0, 1, 2, 4, 5, 8, 3, 7, 6, 9],
+            ),
+            (
+                LinkageFunction::Average,
+                DistanceKind::Euclidean,
+                vec![0, 8, 1, 2, 4, 5, 6, 9, 3, 7],
+            ),
+            (
+                LinkageFunction::Ward,
+                DistanceKind::Euclidean,
+                vec![0, 8, 1, 2, 4, 5, 6, 9, 3, 7],
+            ),
+        ];
+
+        for (linkage, distance, expected_order) in cases {
+            let result = hierarchical_clustering(
+                10,
+                3,
+                data.clone(),
+                ClusteringAxis::Both,
+                linkage,
+                distance,
+                &ClusteringOptions::new(),
+            )
+            .unwrap();
+
+            assert_eq!(result.row_order, expected_order, "{linkage:?}");
+            assert_eq!(result.col_order, vec![0, 1, 2], "{linkage:?}");
+        }
+    }
+
+    // pseudo-random values without exact ties between pair distances
+    fn pseudo_random_values(length: usize, seed: u64) -> Vec<f64> {
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                ((state >> 33) % 1000) as f64 / 100.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nn_chain_matches_lance_williams_test() {
+        let data = pseudo_random_values(30 * 6, 12345);
+        let data_matrix = MatrixView::new(&data, 30, 6);
+        let distances = compute_distance_matrix_from_view(
+            &data_matrix,
+            DistanceMetric::Euclidean,
+        )
+        .unwrap();
+
+        for linkage in [
+            LinkageFunction::Average,
+            LinkageFunction::Ward,
+            LinkageFunction::Complete,
+            LinkageFunction::Weighted,
+        ] {
+            let expected = lance_williams_linkage(
+                CondensedMatrix::new(distances.clone(), 30),
+                linkage,
+            );
+            let merges = nn_chain_linkage(
+                CondensedMatrix::new(distances.clone(), 30),
+                linkage,
+            )
+            .unwrap();
+
+            assert_eq!(merges.len(), expected.len());
+            for (merge, expected) in merges.iter().zip(expected.iter()) {
+                assert_eq!(
+                    (merge.first, merge.second),
+                    (expected.first, expected.second),
+                    "{linkage:?}"
+                );
+                assert!(
+                    (merge.distance - expected.distance).abs() < 1e-9,
+                    "{linkage:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ward_linkage_minimizes_variance_increase_test() {
+        let (nrows, ncols) = (12, 4);
+        let data = pseudo_random_values(nrows * ncols, 2024);
+        let data_matrix = MatrixView::new(&data, nrows, ncols);
+
+        let centroid = |members: &[usize]| -> Vec<f64> {
+            (0..ncols)
+                .map(|j| {
+                    members.iter().map(|&i| data_matrix.get(i, j)).sum::<f64>()
+                        / members.len() as f64
+                })
+                .collect()
+        };
+        // square root of twice the increase in the sum of squares
+        let ward_distance = |first: &[usize], second: &[usize]| -> f64 {
+            let (n_first, n_second) = (first.len() as f64, second.len() as f64);
+            let squared_distance: f64 = centroid(first)
+                .iter()
+                .zip(centroid(second))
+                .map(|(x, y)| (x - y).powi(2))
+                .sum();
+            (2.0 * n_first * n_second / (n_first + n_second) * squared_distance)
+                .sqrt()
+        };
+
+        let merges = compute_merges(
+            CondensedMatrix::new(
+                compute_distance_matrix_from_view(
+                    &data_matrix,
+                    DistanceMetric::Euclidean,
+                )
+                .unwrap(),
+                nrows,
+            ),
+            LinkageFunction::Ward,
+        );
+
+        let mut clusters: Vec<Option<Vec<usize>>> =
+            (0..nrows).map(|i| Some(vec![i])).collect();
+        for merge in merges {
+            let closest = {
+                let active: Vec<&Vec<usize>> =
+                    clusters.iter().flatten().collect();
+                (0..active.len())
+                    .flat_map(|a| (a + 1..active.len()).map(move |b| (a, b)))
+                    .map(|(a, b)| ward_distance(active[a], active[b]))
+                    .fold(f64::INFINITY, f64::min)
+            };
+            let first = clusters[merge.first].take().unwrap();
+            let second = clusters[merge.second].take().unwrap();
+            let height = ward_distance(&first, &second);
+
+            assert!((merge.distance - height).abs() < 1e-9);
+            assert!(height <= closest + 1e-9);
+
+            clusters.push(Some(first.into_iter().chain(second).collect()));
+        }
+    }
+
+    #[test]
+    fn lance_williams_average_merges_test() {
+        // (0,1) (0,2) (0,3) (1,2) (1,3) (2,3)
+        let distances =
+            CondensedMatrix::new(vec![1.0, 3.0, 7.0, 2.0, 6.0, 4.0], 4);
+
+        let merges =
+            lance_williams_linkage(distances, LinkageFunction::Average);
+
+        let expected = vec![
+            engine::Merge {
+                first: 0,
+                second: 1,
+                distance: 1.0,
+            },
+            engine::Merge {
+                first: 2,
+                second: 4,
+                distance: 2.5,
+            },
+            engine::Merge {
+                first: 3,
+                second: 5,
+                distance: 17.0 / 3.0,
+            },
+        ];
+
+        assert_eq!(merges, expected);
+    }
+
+    #[test]
+    fn lance_williams_linkage_methods_test() {
+        // points 0, 1, 3 and 7 on a line
+        // (0,1) (0,2) (0,3) (1,2) (1,3) (2,3)
+        let distances = vec![1.0, 3.0, 7.0, 2.0, 6.0, 4.0];
+
+        let cases = [
+            (LinkageFunction::Complete, [1.0, 3.0, 7.0]),
+            (LinkageFunction::Weighted, [1.0, 2.5, 5.25]),
+            (LinkageFunction::Centroid, [1.0, 2.5, 17.0 / 3.0]),
+            (LinkageFunction::Median, [1.0, 2.5, 5.25]),
+        ];
+
+        for (linkage, expected_heights) in cases {
+            let merges = lance_williams_linkage(
+                CondensedMatrix::new(distances.clone(), 4),
+                linkage,
+            );
+
+            let pairs: Vec<(usize, usize)> = merges
+                .iter()
+                .map(|merge| (merge.first, merge.second))
+                .collect();
+            assert_eq!(pairs, vec![(0, 1), (2, 4), (3, 5)], "{linkage:?}");
+
+            for (merge, expected) in merges.iter().zip(expected_heights) {
+                assert!(
+                    (merge.distance - expected).abs() < 1e-12,
+                    "{linkage:?}: {} != {expected}",
+                    merge.distance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mst_single_linkage_test() {
+        // (0,1) (0,2) (0,3) (1,2) (1,3) (2,3)
+        let distances =
+            CondensedMatrix::new(vec![1.0, 3.0, 7.0, 2.0, 6.0, 4.0], 4);
+
+        let merges = mst_single_linkage(&distances);
+
+        let expected = vec![
+            engine::Merge {
+                first: 0,
+                second: 1,
+                distance: 1.0,
+            },
+            engine::Merge {
+                first: 2,
+                second: 4,
+                distance: 2.0,
+            },
+            engine::Merge {
+                first: 3,
+                second: 5,
+                distance: 4.0,
+            },
+        ];
+
+        assert_eq!(merges, expected);
+
+        // heights of a single linkage tree are the minimum spanning tree
+        // weights, which do not depend on how equal distances are ordered
+        let data = pseudo_random_values(40 * 5, 987);
+        let distances = compute_distance_matrix_from_view(
+            &MatrixView::new(&data, 40, 5),
+            DistanceMetric::Chebyshev,
+        )
+        .unwrap();
+
+        let heights = |merges: Vec<engine::Merge>| -> Vec<f64> {
+            merges.iter().map(|merge| merge.distance).collect()
+        };
+
+        assert_eq!(
+            heights(mst_single_linkage(&CondensedMatrix::new(
+                distances.clone(),
+                40
+            ))),
+            heights(lance_williams_linkage(
+                CondensedMatrix::new(distances, 40),
+                LinkageFunction::Single
+            ))
+        );
+    }
+
+    #[test]
+    fn correlation_distances_test() {
+        let cases = [
+            (
+                DistanceMetric::Pearson,
+                [1.0, 2.0, 3.0],
+                [2.0, 4.0, 6.0],
+                0.0,
+            ),
+            (
+                DistanceMetric::Pearson,
+                [1.0, 2.0, 3.0],
+                [3.0, 2.0, 1.0],
+                2.0,
+            ),
+            (
+                DistanceMetric::Pearson,
+                [1.0, 2.0, 3.0],
+                [1.0, 3.0, 2.0],
+                0.5,
+            ),
+            (
+                DistanceMetric::Spearman,
+                [1.0, 2.0, 3.0],
+                [1.0, 4.0, 9.0],
+                0.0,
+            ),
+            (
+                DistanceMetric::Spearman,
+                [1.0, 2.0, 3.0],
+                [9.0, 4.0, 1.0],
+                2.0,
+            ),
+            // ranks [1, 2, 3] and [1.5, 1.5, 3]
+            (
+                DistanceMetric::Spearman,
+                [1.0, 2.0, 3.0],
+                [5.0, 5.0, 7.0],
+                1.0 - 3.0_f64.sqrt() / 2.0,
+            ),
+            (
+                DistanceMetric::Cosine,
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                1.0,
+            ),
+            (
+                DistanceMetric::Cosine,
+                [1.0, 2.0, 3.0],
+                [2.0, 4.0, 6.0],
+                0.0,
+            ),
+        ];
+
+        for (distance, left, right, expected) in cases {
+            let value = distance.compute(&left, &right).unwrap();
+            assert!(
+                (value - expected).abs() < 1e-12,
+                "{distance:?} {left:?} {right:?}: {value} != {expected}"
+            );
+        }
+
+        assert!(
+            DistanceMetric::Pearson
+                .compute(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0])
+                .unwrap()
+                .is_nan()
+        );
+    }
+
+    #[test]
+    fn manhattan_canberra_minkowski_distances_test() {
+        let left = [1.0, 0.0, 3.0];
+        let right = [4.0, 0.0, -1.0];
+
+        let cases = [
+            (DistanceMetric::Manhattan, 7.0),
+            // 3/5 + 0 (both zero) + 4/4
+            (DistanceMetric::Canberra, 1.6),
+            (DistanceMetric::Minkowski { p: 1.0 }, 7.0),
+            (DistanceMetric::Minkowski { p: 2.0 }, 5.0),
+            (DistanceMetric::Minkowski { p: 3.0 }, 91.0_f64.cbrt()),
+        ];
+
+        for (distance, expected) in cases {
+            let value = distance.compute(&left, &right).unwrap();
+            assert!(
+                (value - expected).abs() < 1e-12,
+                "{distance:?}: {value} != {expected}"
+            );
+        }
+
+        assert!(matches!(
+            DistanceMetric::from_kind(DistanceKind::Minkowski, Some(1.5)),
+            Ok(DistanceMetric::Minkowski { p }) if p == 1.5
+        ));
+        assert!(
+            DistanceMetric::from_kind(DistanceKind::Minkowski, None).is_err()
+        );
+        assert!(
+            DistanceMetric::from_kind(DistanceKind::Minkowski, Some(0.0))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cluster_by_profile_shape_test() {
+        let data: Vec<f64> = vec![
+            1.0, 2.0, 3.0, // row 0
+            30.0, 20.0, 10.0, // row 1
+            10.0, 20.0, 30.0, // row 2
+            3.0, 2.0, 1.0, // row 3
+        ];
+
+        for distance in [
+            DistanceKind::Pearson,
+            DistanceKind::Spearman,
+            DistanceKind::Cosine,
+        ] {
+            let result = cluster_matrix(
+                4,
+                3,
+                data.clone(),
+                ClusteringAxis::Row,
+                LinkageFunction::Average,
+                distance,
+                &ClusteringOptions::new(),
+            )
+            .unwrap();
+
+            let position = |row: usize| {
+                result.row_order.iter().position(|&x| x == row).unwrap()
+            };
+            assert_eq!(
+                position(0).abs_diff(position(2)),
+                1,
+                "{distance:?} {:?}",
+                result.row_order
+            );
+            assert_eq!(
+                position(1).abs_diff(position(3)),
+                1,
+                "{distance:?} {:?}",
+                result.row_order
+            );
+        }
+    }
+
+    #[test]
+    fn euclidean_distance_test() {
+        let left = [1.0, 2.0, 3.0];
+        let right = [4.0, 6.0, 3.0];
+
+        assert_eq!(DistanceMetric::Euclidean.compute(&left, &right), Ok(5.0));
+        assert_eq!(
+            DistanceMetric::SquaredEuclidean.compute(&left, &right),
+            Ok(25.0)
+        );
+    }
+
+    #[test]
+    fn reference_linkage_test() {
+        let data: Vec<f64> = EIGHT_ROWS.to_vec();
+
+        // (left, right, height) of every merge, numbered as in a SciPy
+        // linkage matrix, worked out with the Lance–Williams update of each
+        // method on the Euclidean distances; the data has no ties and no
+        // inversions. scripts/scipy_reference_linkage.py prints the same
+        // cases from SciPy to compare against
+        let cases = [
+            (
+                LinkageFunction::Single,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.31403957322449),
+                    (10, 11, 2.0323385544736388),
+                    (7, 12, 3.1002580537755238),
+                    (9, 13, 3.4618781029955397),
+                ],
+            ),
+            (
+                LinkageFunction::Complete,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.5490319557710874),
+                    (10, 11, 3.3759295016335873),
+                    (7, 9, 4.498922093124086),
+                    (12, 13, 5.139085521763575),
+                ],
+            ),
+            (
+                LinkageFunction::Average,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.4315357644977889),
+                    (10, 11, 2.7987614969770274),
+                    (7, 12, 3.9569916921333883),
+                    (9, 13, 4.265163518071758),
+                ],
+            ),
+            (
+                LinkageFunction::Weighted,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.4315357644977889),
+                    (10, 11, 2.663893710077126),
+                    (7, 12, 3.7900878056054506),
+                    (9, 13, 4.266676920433614),
+                ],
+            ),
+            (
+                LinkageFunction::Centroid,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.4168274418573348),
+                    (10, 11, 2.723128307255789),
+                    (7, 12, 3.731271097092786),
+                    (9, 13, 3.8373012073012394),
+                ],
+            ),
+            (
+                LinkageFunction::Median,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.4168274418573348),
+                    (10, 11, 2.578807864110857),
+                    (7, 12, 3.565773057837529),
+                    (9, 13, 3.7603619340430514),
+                ],
+            ),
+            (
+                LinkageFunction::Ward,
+                vec![
+                    (0, 2, 0.47201694884823775),
+                    (3, 5, 0.6490762667052308),
+                    (1, 4, 0.726773692424265),
+                    (6, 8, 1.636011409903162),
+                    (7, 10, 3.740989174001978),
+                    (11, 12, 5.19672974090437),
+                    (9, 13, 6.646400654991139),
+                ],
+            ),
+        ];
+
+        let distances = compute_distance_matrix_from_view(
+            &MatrixView::new(&data, 8, 3),
+            DistanceMetric::Euclidean,
+        )
+        .unwrap();
+
+        for (linkage, expected) in cases {
+            let merges = compute_merges(
+                CondensedMatrix::new(distances.clone(), 8),
+                linkage,
+            );
+
+            assert_eq!(merges.len(), expected.len(), "{linkage:?}");
+            for (merge, (first, second, height)) in merges.iter().zip(expected)
+            {
+                assert_eq!(
+                    (merge.first, merge.second),
+                    (first, second),
+                    "{linkage:?}"
+                );
+                assert!(
+                    (merge.distance - height).abs() < 1e-12 * height,
+                    "{linkage:?}: {} != {height}",
+                    merge.distance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn linkage_matrix_test() {
+        let data: Vec<f64> = EIGHT_ROWS.to_vec();
+
+        let result = cluster_matrix(
+            8,
+            3,
+            data,
+            ClusteringAxis::Row,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &ClusteringOptions::new(),
+        )
+        .unwrap();
+
+        let expected = [
+            [0.0, 2.0, 0.47201694884823775, 2.0],
+            [3.0, 5.0, 0.6490762667052308, 2.0],
+            [1.0, 4.0, 0.726773692424265, 2.0],
+            [6.0, 8.0, 1.4315357644977889, 3.0],
+            [10.0, 11.0, 2.7987614969770274, 5.0],
+            [7.0, 12.0, 3.9569916921333883, 6.0],
+            [9.0, 13.0, 4.265163518071758, 8.0],
+        ];
+
+        let row_linkage =
+            result.row_tree.as_ref().unwrap().tree.linkage_matrix();
+
+        assert_eq!(row_linkage.len(), expected.len() * 4);
+        for (row, expected) in row_linkage.chunks(4).zip(expected) {
+            assert_eq!(
+                (row[0], row[1], row[3]),
+                (expected[0], expected[1], expected[3])
+            );
+            assert!((row[2] - expected[2]).abs() < 1e-12);
+        }
+
+        assert!(result.col_tree.is_none());
+    }
+
+    // (((0, 1) at 1, 2) at 3, 3) at 4, leaves in the order 3, 2, 0, 1
+    fn four_leaf_tree() -> HcTree {
+        let merges = [
+            Merge {
+                first: 0,
+                second: 1,
+                distance: 1.0,
+            },
+            Merge {
+                first: 2,
+                second: 4,
+                distance: 3.0,
+            },
+            Merge {
+                first: 3,
+                second: 5,
+                distance: 4.0,
+            },
+        ];
+
+        HcTree::from_merges(4, &merges)
+    }
+
+    #[test]
+    fn newick_test() {
+        let tree = four_leaf_tree();
+
+        assert_eq!(tree.to_newick(&[]), "(3:4,(2:3,(0:1,1:1):2):1);");
+
+        let labels: Vec<String> = ["A", "gene one", "it's", "D"]
+            .iter()
+            .map(|label| label.to_string())
+            .collect();
+
+        let ladderized = tree_from_linkage(4, &tree.linkage_matrix())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ladderized.to_newick(&labels),
+            "(((A:1,'gene one':1):2,'it''s':3):1,D:4);"
+        );
+        assert!(tree_from_linkage(4, &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn optimal_leaf_ordering_test() {
+        let data = pseudo_random_values(8 * 4, 2024);
+        let data_matrix = MatrixView::new(&data, 8, 4);
+        let distances = CondensedMatrix::new(
+            compute_distance_matrix_from_view(
+                &data_matrix,
+                DistanceMetric::Euclidean,
+            )
+            .unwrap(),
+            8,
+        );
+        let path_length = |order: &[usize]| -> f64 {
+            order
+                .windows(2)
+                .map(|pair| distances.get(pair[0], pair[1]))
+                .sum()
+        };
+
+        let ladderized = build_tree_with_views(
+            &data_matrix,
+            DistanceMetric::Euclidean,
+            LinkageFunction::Average,
+            LeafOrdering::LadderizeDescending,
+            None,
+        )
+        .unwrap();
+        let optimal = build_tree_with_views(
+            &data_matrix,
+            DistanceMetric::Euclidean,
+            LinkageFunction::Average,
+            LeafOrdering::Optimal,
+            None,
+        )
+        .unwrap();
+
+        // only the children order changes, not the hierarchy
+        assert_eq!(ladderized.linkage_matrix(), optimal.linkage_matrix());
+
+        // brute force over every combination of flipped merges
+        let mut best = f64::INFINITY;
+        for flips in 0..(1_usize << 7) {
+            let mut order: Vec<usize> = Vec::with_capacity(8);
+            let mut stack: Vec<usize> = vec![14];
+            while let Some(node_id) = stack.pop() {
+                let children = &ladderized.nodes[node_id].children;
+                if children.is_empty() {
+                    order.push(node_id);
+                } else if flips & (1 << (node_id - 8)) == 0 {
+                    stack.extend([children[1], children[0]]);
+                } else {
+                    stack.extend([children[0], children[1]]);
+                }
+            }
+            best = best.min(path_length(&order));
+        }
+
+        let optimal_order = leaf_order(&optimal);
+        assert!((path_length(&optimal_order) - best).abs() < 1e-12);
+        assert!(
+            path_length(&optimal_order) < path_length(&leaf_order(&ladderized))
+        );
+
+        // refused before clustering once the cost table gets too large
+        let size = MAX_OPTIMAL_LEAVES + 1;
+        let distances =
+            CondensedMatrix::new(vec![1.0; size * (size - 1) / 2], size);
+        assert_eq!(
+            build_tree(
+                distances,
+                LinkageFunction::Average,
+                LeafOrdering::Optimal,
+                None
+            )
+            .err(),
+            Some(ClusteringError::TooManyLeaves {
+                count: size,
+                limit: MAX_OPTIMAL_LEAVES
+            })
+        );
+    }
+
+    #[test]
+    fn leaf_ordering_strategies_test() {
+        let tree = four_leaf_tree();
+        assert_eq!(leaf_order(&tree), vec![3, 2, 0, 1]);
+
+        let mut tree = four_leaf_tree();
+        tree.ladderize();
+        assert_eq!(leaf_order(&tree), vec![0, 1, 2, 3]);
+
+        let mut tree = four_leaf_tree();
+        tree.ladderize_ascending();
+        assert_eq!(leaf_order(&tree), vec![3, 2, 0, 1]);
+
+        // subtree weights: {0, 1} = 5, {0, 1, 2} = 8
+        let mut tree = four_leaf_tree();
+        tree.reorder_by_weights(&[4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(leaf_order(&tree), vec![3, 2, 1, 0]);
+
+        let data: Vec<f64> = vec![
+            10.1, 10.1, // row 0
+            0.0, 0.0, // row 1
+            10.0, 10.0, // row 2
+            0.1, 0.1, // row 3
+        ];
+        let cluster = |options: &ClusteringOptions| {
+            cluster_matrix(
+                4,
+                2,
+                data.clone(),
+                ClusteringAxis::Row,
+                LinkageFunction::Average,
+                DistanceKind::Euclidean,
+                options,
+            )
+            .unwrap()
+            .row_order
+        };
+
+        let mut options = ClusteringOptions::new();
+        options.ordering = LeafOrdering::MeanValue;
+        assert_eq!(cluster(&options), vec![1, 3, 2, 0]);
+
+        options.ordering = LeafOrdering::Weights;
+        options.row_weights = Some(vec![-10.1, 0.0, -10.0, -0.1]);
+        assert_eq!(cluster(&options), vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn cut_tree_test() {
+        let tree = four_leaf_tree();
+
+        assert_eq!(tree.cut_by_count(1), vec![1, 1, 1, 1]);
+        assert_eq!(tree.cut_by_count(2), vec![1, 1, 1, 2]);
+        assert_eq!(tree.cut_by_count(3), vec![1, 1, 2, 3]);
+        assert_eq!(tree.cut_by_count(4), vec![1, 2, 3, 4]);
+        assert_eq!(tree.cut_by_count(10), vec![1, 2, 3, 4]);
+
+        assert_eq!(tree.cut_by_height(0.5), vec![1, 2, 3, 4]);
+        assert_eq!(tree.cut_by_height(1.0), vec![1, 1, 2, 3]);
+        assert_eq!(tree.cut_by_height(3.5), vec![1, 1, 1, 2]);
+        assert_eq!(tree.cut_by_height(4.0), vec![1, 1, 1, 1]);
+
+        // labels follow the input order, not the merge order
+        let tree = HcTree::from_merges(
+            4,
+            &[
+                Merge {
+                    first: 2,
+                    second: 3,
+                    distance: 1.0,
+                },
+                Merge {
+                    first: 1,
+                    second: 4,
+                    distance: 2.0,
+                },
+                Merge {
+                    first: 0,
+                    second: 5,
+                    distance: 3.0,
+                },
+            ],
+        );
+        assert_eq!(tree.cut_by_count(2), vec![1, 2, 2, 2]);
+        assert_eq!(tree.cut_by_count(3), vec![1, 2, 3, 3]);
+
+        // a centroid style inversion: the later merge sits lower
+        let tree = HcTree::from_merges(
+            3,
+            &[
+                Merge {
+                    first: 0,
+                    second: 1,
+                    distance: 2.0,
+                },
+                Merge {
+                    first: 2,
+                    second: 3,
+                    distance: 1.5,
+                },
+            ],
+        );
+        assert_eq!(tree.cut_by_height(1.5), vec![1, 2, 3]);
+        assert_eq!(tree.cut_by_height(2.0), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn dynamic_tree_cut_test() {
+        // three tight groups of eight rows, interleaved in input order
+        let centers = [[0.0, 0.0, 0.0], [5.0, 0.0, 0.0], [0.0, 5.0, 0.0]];
+        let jitter = pseudo_random_values(24 * 3, 99);
+        let data: Vec<f64> = (0..24)
+            .flat_map(|row| (0..3).map(move |col| (row, col)))
+            .map(|(row, col)| {
+                centers[row % 3][col] + jitter[row * 3 + col] / 100.0
+            })
+            .collect();
 
-    for (i, first_node) in nodes.iter().enumerate() {
-        if first_node.parent.is_some() {
-            continue;
+        let modules = |method: DynamicCutMethod,
+                       min_module_size: usize,
+                       deep_split: u8| {
+            let mut dynamic_cut = DynamicCutOptions::new(method);
+            dynamic_cut.min_module_size = min_module_size;
+            dynamic_cut.deep_split = deep_split;
+
+            let mut options = ClusteringOptions::new();
+            options.dynamic_cut = Some(dynamic_cut);
+
+            cluster_matrix(
+                24,
+                3,
+                data.clone(),
+                ClusteringAxis::Row,
+                LinkageFunction::Average,
+                DistanceKind::Euclidean,
+                &options,
+            )
+            .unwrap()
+            .row_modules
+        };
+        let group_labels = |labels: &[usize]| -> Vec<Vec<usize>> {
+            (0..3)
+                .map(|group| {
+                    let mut found: Vec<usize> =
+                        labels.iter().skip(group).step_by(3).copied().collect();
+                    found.dedup();
+                    found
+                })
+                .collect()
+        };
+
+        for (method, deep_split) in
+            [(DynamicCutMethod::Hybrid, 1), (DynamicCutMethod::Tree, 0)]
+        {
+            let labels = modules(method, 5, deep_split);
+            let mut groups: Vec<usize> = group_labels(&labels)
+                .into_iter()
+                .map(|found| {
+                    assert_eq!(found.len(), 1, "{method:?}");
+                    found[0]
+                })
+                .collect();
+            groups.sort();
+            assert_eq!(groups, vec![1, 2, 3], "{method:?}");
         }
-        for second_node in nodes.iter().skip(i + 1) {
-            if second_node.parent.is_some() {
-                continue;
-            }
 
-            pairs.push(LinkageResult {
-                first_index: first_node.id,
-                second_index: second_node.id,
-                distance: linkage.compute_from_views(
-                    first_node,
-                    second_node,
-                    distance_matrix,
-                    data_matrix,
-                ),
-            });
-        }
+        // groups smaller than a module: the closest two are absorbed into
+        // one module, the last one is left out
+        let labels = modules(DynamicCutMethod::Hybrid, 10, 1);
+        assert_eq!(group_labels(&labels), vec![vec![1], vec![1], vec![0]]);
     }
 
-    let best_pair = pairs
-        .iter()
-        .reduce(|acc, current| {
-            if current.distance < acc.distance {
-                current
-            } else {
-                acc
-            }
-        })
+    #[test]
+    fn cophenetic_correlation_test() {
+        let tree = four_leaf_tree();
+        let cophenetic = tree.cophenetic_distances();
+
+        assert_eq!(cophenetic.as_slice(), &[1.0, 3.0, 4.0, 3.0, 4.0, 4.0]);
+        assert!(
+            (cophenetic_correlation(&tree, &cophenetic) - 1.0).abs() < 1e-12
+        );
+
+        // points 0, 1 and 3 on a line: average linkage joins 0 and 1 at 1,
+        // then 2 at 2.5, against distances 1, 3 and 2
+        let mut options = ClusteringOptions::new();
+        options.cophenetic_correlation = true;
+        let result = cluster_matrix(
+            3,
+            1,
+            vec![0.0, 1.0, 3.0],
+            ClusteringAxis::Row,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &options,
+        )
         .unwrap();
 
-    let indices: Vec<usize> = nodes[best_pair.first_index]
-        .indices
-        .iter()
-        .chain(nodes[best_pair.second_index].indices.iter())
-        .cloned()
-        .collect();
+        let correlation = result.row_cophenetic_correlation().unwrap();
+        assert!((correlation - 3.0_f64.sqrt() / 2.0).abs() < 1e-12);
+        assert_eq!(result.col_cophenetic_correlation(), None);
+    }
 
-    let next_index = nodes.len();
-    nodes[best_pair.first_index].update_parent(next_index);
-    nodes[best_pair.second_index].update_parent(next_index);
+    #[test]
+    fn nan_policy_test() {
+        let euclidean = DistanceMetric::Euclidean
+            .compute_pairwise_complete(
+                &[1.0, f64::NAN, 3.0],
+                &[2.0, 5.0, f64::NAN],
+            )
+            .unwrap();
+        assert!((euclidean - 3.0_f64.sqrt()).abs() < 1e-12);
+
+        let left = [1.0, f64::NAN, 3.0, 4.0];
+        let right = [2.0, 5.0, 1.0, f64::NAN];
+        // complete positions 0 and 2, half of the profile
+        assert_eq!(
+            DistanceMetric::Manhattan.compute_pairwise_complete(&left, &right),
+            Ok(6.0)
+        );
+        assert_eq!(
+            DistanceMetric::Chebyshev.compute_pairwise_complete(&left, &right),
+            Ok(2.0)
+        );
+        assert!(
+            DistanceMetric::Euclidean
+                .compute_pairwise_complete(&[f64::NAN, 1.0], &[2.0, f64::NAN])
+                .unwrap()
+                .is_nan()
+        );
 
-    nodes.push(Node {
-        id: next_index,
-        parent: None,
-        children: vec![best_pair.first_index, best_pair.second_index],
-        indices,
-    });
-}
+        let data: Vec<f64> = vec![
+            1.0,
+            2.0,
+            3.0, // row 0
+            1.5,
+            2.5,
+            f64::NAN, // row 1
+            f64::NAN,
+            f64::NAN,
+            f64::NAN, // row 2
+            8.0,
+            8.0,
+            9.0, // row 3
+            7.0,
+            9.0,
+            8.0, // row 4
+        ];
+        let labels: Vec<String> =
+            ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+
+        let cluster = |nan_policy: NanPolicy| {
+            let mut options = ClusteringOptions::new();
+            options.nan_policy = nan_policy;
+            options.dynamic_cut = Some(DynamicCutOptions {
+                min_module_size: 2,
+                ..DynamicCutOptions::new(DynamicCutMethod::Tree)
+            });
+            cluster_matrix(
+                5,
+                3,
+                data.clone(),
+                ClusteringAxis::Both,
+                LinkageFunction::Average,
+                DistanceKind::Euclidean,
+                &options,
+            )
+            .unwrap()
+        };
+
+        let result = cluster(NanPolicy::PairwiseComplete);
+        let row_tree = result.row_tree.as_ref().unwrap();
+        assert_eq!(row_tree.placed, vec![0, 1, 3, 4]);
+        assert_eq!(row_tree.unplaced, vec![2]);
+        assert_eq!(result.row_order.len(), 5);
+        assert_eq!(result.row_order[4], 2);
+        assert_eq!(
+            row_tree.expand(&row_tree.tree.cut_by_count(2)),
+            vec![1, 1, 0, 2, 2]
+        );
+        assert_eq!(result.row_modules.len(), 5);
+        assert_eq!(result.row_modules[2], 0);
+        assert!(
+            !result
+                .row_newick(labels.clone())
+                .unwrap()
+                .unwrap()
+                .contains('c')
+        );
+        assert!(result.col_tree.as_ref().unwrap().unplaced.is_empty());
+        // the displayed values keep their gaps
+        assert_eq!(result.values.iter().filter(|x| x.is_nan()).count(), 4);
+
+        let result = cluster(NanPolicy::DropIncomplete);
+        assert_eq!(result.row_tree.as_ref().unwrap().unplaced, vec![1, 2]);
+        assert_eq!(&result.row_order[3..], &[1, 2]);
+        // every column misses row 2
+        assert_eq!(result.col_tree.as_ref().unwrap().unplaced, vec![0, 1, 2]);
+
+        let result = cluster(NanPolicy::ImputeRowMean);
+        assert_eq!(result.row_tree.as_ref().unwrap().unplaced, vec![2]);
+        assert_eq!(result.values.iter().filter(|x| x.is_nan()).count(), 4);
+    }
 
-struct LinkageResult {
-    first_index: usize,
-    second_index: usize,
-    distance: f64,
-}
+    #[test]
+    fn clustering_errors_test() {
+        let cluster = |nrows: usize,
+                       ncols: usize,
+                       values: Vec<f64>,
+                       axis: ClusteringAxis,
+                       distance: DistanceKind,
+                       options: &ClusteringOptions| {
+            cluster_matrix(
+                nrows,
+                ncols,
+                values,
+                axis,
+                LinkageFunction::Average,
+                distance,
+                options,
+            )
+            .err()
+        };
+        let options = ClusteringOptions::new();
+        let values: Vec<f64> = (0..6).map(f64::from).collect();
 
-#[wasm_bindgen]
-pub struct HierarchicalClusteringResult {
-    row_order: Vec<usize>,
-    col_order: Vec<usize>,
-    values: Vec<f64>,
-}
+        assert_eq!(
+            cluster(
+                4,
+                2,
+                values.clone(),
+                ClusteringAxis::Row,
+                DistanceKind::Euclidean,
+                &options
+            ),
+            Some(ClusteringError::DimensionMismatch {
+                what: "values",
+                expected: 8,
+                actual: 6,
+            })
+        );
+        assert_eq!(
+            cluster(
+                6,
+                1,
+                values.clone(),
+                ClusteringAxis::Row,
+                DistanceKind::Pearson,
+                &options
+            ),
+            Some(ClusteringError::TooFewObservations {
+                axis: "rows",
+                count: 1,
+            })
+        );
 
-#[wasm_bindgen]
-impl HierarchicalClusteringResult {
-    #[wasm_bindgen(constructor)]
-    pub fn new(
-        row_order: Vec<usize>,
-        col_order: Vec<usize>,
-        values: Vec<f64>,
-    ) -> Self {
-        HierarchicalClusteringResult {
-            row_order,
-            col_order,
-            values,
-        }
-    }
+        let mut infinite = values.clone();
+        infinite[3] = f64::NEG_INFINITY;
+        assert_eq!(
+            cluster(
+                3,
+                2,
+                infinite,
+                ClusteringAxis::Row,
+                DistanceKind::Euclidean,
+                &options
+            ),
+            Some(ClusteringError::NonFiniteInput {
+                row: 1,
+                col: 1,
+                value: f64::NEG_INFINITY,
+            })
+        );
 
-    #[wasm_bindgen(getter)]
-    pub fn row_order(&self) -> Uint32Array {
-        let converted: Vec<u32> =
-            self.row_order.iter().map(|&x| x as u32).collect();
-        Uint32Array::from(converted.as_slice())
-    }
+        assert!(matches!(
+            cluster(
+                3,
+                2,
+                values.clone(),
+                ClusteringAxis::Row,
+                DistanceKind::Minkowski,
+                &options
+            ),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+
+        let mut weighted = ClusteringOptions::new();
+        weighted.ordering = LeafOrdering::Weights;
+        assert!(matches!(
+            cluster(
+                3,
+                2,
+                values.clone(),
+                ClusteringAxis::Row,
+                DistanceKind::Euclidean,
+                &weighted
+            ),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+        // column weights are not needed when only rows are clustered
+        weighted.row_weights = Some(vec![1.0, 2.0, 3.0]);
+        assert_eq!(
+            cluster(
+                3,
+                2,
+                values.clone(),
+                ClusteringAxis::Row,
+                DistanceKind::Euclidean,
+                &weighted
+            ),
+            None
+        );
+        weighted.col_weights = Some(vec![1.0]);
+        assert_eq!(
+            cluster(
+                3,
+                2,
+                values.clone(),
+                ClusteringAxis::Both,
+                DistanceKind::Euclidean,
+                &weighted
+            ),
+            Some(ClusteringError::DimensionMismatch {
+                what: "column weights",
+                expected: 2,
+                actual: 1,
+            })
+        );
 
-    #[wasm_bindgen(getter)]
-    pub fn col_order(&self) -> Uint32Array {
-        let converted: Vec<u32> =
-            self.col_order.iter().map(|&x| x as u32).collect();
-        Uint32Array::from(converted.as_slice())
+        assert!(matches!(
+            tree_from_linkage(3, &[0.0, 1.0, 1.0, 2.0]),
+            Err(ClusteringError::DimensionMismatch { .. })
+        ));
+        // the second row merges leaf 1 again
+        assert!(matches!(
+            tree_from_linkage(3, &[0.0, 1.0, 1.0, 2.0, 1.0, 2.0, 2.0, 2.0]),
+            Err(ClusteringError::UnsupportedOption(_))
+        ));
+        assert_eq!(
+            ClusteringError::TooFewObservations {
+                axis: "columns",
+                count: 0,
+            }
+            .to_string(),
+            "correlation between columns needs profiles of at least 2 \
+             values, got 0"
+        );
     }
 
-    #[wasm_bindgen(getter)]
-    pub fn values(&self) -> Float64Array {
-        Float64Array::from(self.values.as_slice())
-    }
-}
+    #[test]
+    fn degenerate_inputs_test() {
+        let mut options = ClusteringOptions::new();
+        options.cophenetic_correlation = true;
+        options.dynamic_cut = Some(DynamicCutOptions {
+            min_module_size: 1,
+            ..DynamicCutOptions::new(DynamicCutMethod::Hybrid)
+        });
 
-pub fn compute_distance_matrix_from_view(
-    data_matrix: &MatrixView,
-    distance: DistanceMetric,
-) -> Vec<f64> {
-    let mut distance_matrix: Vec<f64> = Vec::<f64>::with_capacity(
-        data_matrix.nrows() * (data_matrix.nrows() - 1) / 2,
-    );
+        for ordering in [
+            LeafOrdering::LadderizeDescending,
+            LeafOrdering::MeanValue,
+            LeafOrdering::Optimal,
+        ] {
+            options.ordering = ordering;
+
+            for (nrows, ncols) in [(0, 3), (1, 3), (3, 0), (3, 1), (0, 0)] {
+                let result = cluster_matrix(
+                    nrows,
+                    ncols,
+                    (0..nrows * ncols).map(|x| x as f64).collect(),
+                    ClusteringAxis::Both,
+                    LinkageFunction::Ward,
+                    DistanceKind::Euclidean,
+                    &options,
+                )
+                .unwrap();
+
+                for (axis, order, n) in [
+                    (&result.row_tree, &result.row_order, nrows),
+                    (&result.col_tree, &result.col_order, ncols),
+                ] {
+                    let axis = axis.as_ref().unwrap();
+                    if n < 2 {
+                        // nothing to merge, the trivial tree keeps the order
+                        assert_eq!(*order, (0..n).collect::<Vec<_>>());
+                        assert!(axis.tree.linkage_matrix().is_empty());
+                        assert!(
+                            axis.tree
+                                .dendrogram_segments(
+                                    DendrogramOrientation::Vertical
+                                )
+                                .is_empty()
+                        );
+                        assert_eq!(axis.tree.cut_by_count(1), vec![1; n]);
+                        assert_eq!(axis.to_newick(&[]).unwrap(), {
+                            if n == 0 { ";" } else { "0;" }
+                        });
+                    }
+                    assert_eq!(axis.placed.len(), n);
+                }
+
+                assert_eq!(result.values.len(), nrows * ncols);
+                if nrows < 2 {
+                    assert_eq!(result.row_modules, vec![0; nrows]);
+                    assert!(
+                        result.row_cophenetic_correlation.unwrap().is_nan()
+                    );
+                }
+            }
+        }
 
-    for i in 0..data_matrix.nrows() {
-        let row_i_vect: Vec<f64> = data_matrix.row(i);
-        for j in i + 1..data_matrix.nrows() {
-            let row_j_vect: Vec<f64> = data_matrix.row(j);
-            distance_matrix
-                .push(distance.compute(&row_i_vect, &row_j_vect).unwrap());
+        // two rows, the second one a duplicate of the first: a single merge
+        // at height zero
+        let result = cluster_matrix(
+            2,
+            3,
+            vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0],
+            ClusteringAxis::Row,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &ClusteringOptions::new(),
+        )
+        .unwrap();
+        let row_tree = &result.row_tree.as_ref().unwrap().tree;
+        assert_eq!(result.row_order, vec![0, 1]);
+        assert_eq!(row_tree.linkage_matrix(), vec![0.0, 1.0, 0.0, 2.0]);
+        assert_eq!(row_tree.to_newick(&[]), "(0:0,1:0);");
+        assert_eq!(row_tree.cut_by_height(0.0), vec![1, 1]);
+
+        let result = cluster_matrix(
+            2,
+            2,
+            vec![0.0, 0.0, 3.0, 4.0],
+            ClusteringAxis::Row,
+            LinkageFunction::Single,
+            DistanceKind::Euclidean,
+            &ClusteringOptions::new(),
+        )
+        .unwrap();
+        let row_tree = &result.row_tree.as_ref().unwrap().tree;
+        assert_eq!(row_tree.linkage_matrix(), vec![0.0, 1.0, 5.0, 2.0]);
+        assert_eq!(row_tree.cut_by_count(2), vec![1, 2]);
+        assert_eq!(
+            row_tree.dendrogram_segments(DendrogramOrientation::Vertical),
+            vec![0.5, 0.0, 0.5, 5.0, 1.5, 5.0, 1.5, 0.0]
+        );
+
+        for nrows in 0..3 {
+            let data: Vec<f64> = (0..nrows * 2).map(|x| x as f64).collect();
+            let order = cluster_with_views(
+                &MatrixView::new(&data, nrows, 2),
+                DistanceMetric::Euclidean,
+                LinkageFunction::Complete,
+            )
+            .unwrap();
+            assert_eq!(order, (0..nrows).collect::<Vec<_>>());
         }
     }
 
-    distance_matrix
-}
+    #[test]
+    fn dendrogram_segments_test() {
+        let mut tree = four_leaf_tree();
+        tree.ladderize();
+
+        // leaf order 0, 1, 2, 3
+        let expected_vertical = vec![
+            0.5, 0.0, 0.5, 1.0, 1.5, 1.0, 1.5, 0.0, // (0, 1)
+            1.0, 1.0, 1.0, 3.0, 2.5, 3.0, 2.5, 0.0, // ((0, 1), 2)
+            1.75, 3.0, 1.75, 4.0, 3.5, 4.0, 3.5, 0.0, // (((0, 1), 2), 3)
+        ];
 
-pub fn cluster_with_views(
-    data_matrix: &MatrixView,
-    distance: DistanceMetric,
-    linkage: LinkageFunction,
-) -> Vec<usize> {
-    let mut tree = HcTree::initialize_new_tree(data_matrix.nrows());
+        assert_eq!(
+            tree.dendrogram_segments(DendrogramOrientation::Vertical),
+            expected_vertical
+        );
 
-    let distance_matrix_flat =
-        compute_distance_matrix_from_view(data_matrix, distance);
+        let expected_horizontal: Vec<f64> = expected_vertical
+            .chunks(2)
+            .flat_map(|point| [point[1], point[0]])
+            .collect();
 
-    let distance_matrix = MatrixView::new_upper_triangular(
-        &distance_matrix_flat,
-        data_matrix.nrows(),
-        data_matrix.nrows(),
-    );
+        assert_eq!(
+            tree.dendrogram_segments(DendrogramOrientation::Horizontal),
+            expected_horizontal
+        );
+    }
 
-    loop {
-        let num_nodes_without_parent =
-            tree.nodes.iter().filter(|x| x.parent.is_none()).count();
+    #[test]
+    fn tie_breaking_test() {
+        let a = [1.0, 2.0, 3.0];
+        let b = [4.0, 0.0, 1.0];
+        let c = [2.0, 2.0, 2.0];
+        // rows 0, 2 and 5, rows 1 and 4, and rows 3 and 6 are duplicates
+        let data: Vec<f64> = [a, b, a, c, b, a, c].concat();
+
+        let cluster = |linkage: LinkageFunction, ordering: LeafOrdering| {
+            let mut options = ClusteringOptions::new();
+            options.ordering = ordering;
+            cluster_matrix(
+                7,
+                3,
+                data.clone(),
+                ClusteringAxis::Row,
+                linkage,
+                DistanceKind::Euclidean,
+                &options,
+            )
+            .unwrap()
+        };
+
+        // single linkage merges in the order of SciPy's spanning tree
+        // algorithm, the others the lowest node ids among the closest pairs
+        // first; only the last two heights are not zero
+        let lowest_pairs = [(0, 2), (1, 4), (3, 6), (5, 7), (9, 10), (8, 11)];
+        let cases = [
+            (
+                LinkageFunction::Single,
+                [(0, 2), (5, 7), (3, 6), (1, 4), (8, 9), (10, 11)],
+                [std::f64::consts::SQRT_2, 3.0],
+                // `leaves_list` of the same linkage
+                vec![1, 4, 5, 0, 2, 3, 6],
+            ),
+            (
+                LinkageFunction::Complete,
+                lowest_pairs,
+                [std::f64::consts::SQRT_2, 4.123105625617661],
+                vec![1, 4, 3, 6, 5, 0, 2],
+            ),
+            (
+                LinkageFunction::Average,
+                lowest_pairs,
+                [std::f64::consts::SQRT_2, 3.6738633753705963],
+                vec![1, 4, 3, 6, 5, 0, 2],
+            ),
+            (
+                LinkageFunction::Weighted,
+                lowest_pairs,
+                [std::f64::consts::SQRT_2, 3.5615528128088303],
+                vec![1, 4, 3, 6, 5, 0, 2],
+            ),
+            (
+                LinkageFunction::Ward,
+                lowest_pairs,
+                [2.1908902300206647, 6.169047159581685],
+                vec![1, 4, 3, 6, 5, 0, 2],
+            ),
+            (
+                LinkageFunction::Centroid,
+                lowest_pairs,
+                [std::f64::consts::SQRT_2, 3.6496575181789317],
+                vec![1, 4, 3, 6, 5, 0, 2],
+            ),
+        ];
 
-        // println!("Nodes remaining: {}", num_nodes_without_parent);
+        for (linkage, pairs, heights, merge_order) in cases {
+            let result = cluster(linkage, LeafOrdering::MergeOrder);
+            let merges =
+                result.row_tree.as_ref().unwrap().tree.linkage_matrix();
+
+            for (k, row) in merges.chunks(4).enumerate() {
+                assert_eq!(
+                    (row[0] as usize, row[1] as usize),
+                    pairs[k],
+                    "{linkage:?}"
+                );
+                let height = k.checked_sub(4).map_or(0.0, |k| heights[k]);
+                assert!((row[2] - height).abs() < 1e-12, "{linkage:?}");
+            }
+            assert_eq!(result.row_order, merge_order, "{linkage:?}");
 
-        if num_nodes_without_parent < 2 {
-            break;
+            // equally large subtrees keep the lower node id first
+            let result = cluster(linkage, LeafOrdering::LadderizeDescending);
+            assert_eq!(result.row_order, vec![0, 2, 5, 3, 6, 1, 4]);
         }
 
-        find_nodes_to_merge_with_view(
-            &mut tree.nodes,
-            &distance_matrix,
-            &data_matrix,
-            linkage,
+        // ladderizing does not depend on the child order it starts from
+        let distances = CondensedMatrix::new(
+            compute_distance_matrix_from_view(
+                &MatrixView::new(&data, 7, 3),
+                DistanceMetric::Euclidean,
+            )
+            .unwrap(),
+            7,
         );
+        let mut tree = HcTree::from_merges(
+            7,
+            &compute_merges(distances, LinkageFunction::Average),
+        );
+        tree.reorder_by_weights(&[7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0]);
+        tree.ladderize();
+        assert_eq!(leaf_order(&tree), vec![0, 2, 5, 3, 6, 1, 4]);
+        tree.ladderize_ascending();
+        assert_eq!(leaf_order(&tree), vec![1, 4, 3, 6, 5, 0, 2]);
     }
 
-    let row_order: Vec<usize> = tree
-        .ladderized_preorder_node_view()
-        .filter(|node| node.children.len() == 0)
-        .map(|node| node.id)
-        .collect();
+    #[test]
+    fn precomputed_distance_matrix_test() {
+        let data: Vec<f64> = TEN_ROWS.to_vec();
+        let condensed = compute_distance_matrix_from_view(
+            &MatrixView::new(&data, 10, 3),
+            DistanceMetric::Euclidean,
+        )
+        .unwrap();
+        let distances = CondensedMatrix::new(condensed.clone(), 10);
+        let square: Vec<f64> = (0..10)
+            .flat_map(|i| {
+                let distances = &distances;
+                (0..10).map(
+                    move |j| {
+                        if i == j { 0.0 } else { distances.get(i, j) }
+                    },
+                )
+            })
+            .collect();
 
-    row_order
-}
+        let mut options = ClusteringOptions::new();
+        options.cophenetic_correlation = true;
+        let from_values = cluster_matrix(
+            10,
+            3,
+            data.clone(),
+            ClusteringAxis::Row,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &options,
+        )
+        .unwrap();
 
-#[wasm_bindgen]
-pub fn hierarchical_clustering(
-    nrows: usize,
-    ncols: usize,
-    values: Vec<f64>,
-    axis: ClusteringAxis,
-    linkage: LinkageFunction,
-    distance: DistanceMetric,
-) -> HierarchicalClusteringResult {
-    let data_matrix = MatrixView::new(&values, nrows, ncols);
+        for (layout, input) in [
+            (DistanceMatrixLayout::Condensed, &condensed),
+            (DistanceMatrixLayout::Square, &square),
+        ] {
+            let result = cluster_distance_matrix(
+                10,
+                input,
+                layout,
+                LinkageFunction::Average,
+                &options,
+            )
+            .unwrap();
+
+            assert_eq!(result.row_order, from_values.row_order);
+            assert_eq!(result.col_order, result.row_order);
+            assert!(result.col_tree.is_none());
+            assert_eq!(
+                result.row_cophenetic_correlation,
+                from_values.row_cophenetic_correlation
+            );
 
-    let (row_order, col_order) = match axis {
-        ClusteringAxis::Both => (
-            cluster_with_views(&data_matrix, distance, linkage),
-            cluster_with_views(&data_matrix.transposed(), distance, linkage),
-        ),
-
-        ClusteringAxis::Row => (
-            cluster_with_views(&data_matrix, distance, linkage),
-            (0..ncols).collect(),
-        ),
-
-        ClusteringAxis::Column => (
-            (0..nrows).collect(),
-            cluster_with_views(&data_matrix.transposed(), distance, linkage),
-        ),
-    };
+            // the distance matrix itself, in the clustered order
+            let order = &result.row_order;
+            assert_eq!(result.values.len(), 100);
+            assert_eq!(result.values[0], 0.0);
+            assert_eq!(result.values[1], distances.get(order[0], order[1]));
+            assert_eq!(result.values[10], result.values[1]);
+        }
 
-    HierarchicalClusteringResult {
-        values: {
-            let permutation_view = data_matrix
-                .permutation(row_order.as_slice(), col_order.as_slice());
-            (0..permutation_view.nrows())
-                .flat_map(|i| permutation_view.row(i))
-                .collect()
-        },
-        row_order,
-        col_order,
+        let reject = |size: usize,
+                      input: &[f64],
+                      layout: DistanceMatrixLayout,
+                      options: &ClusteringOptions| {
+            cluster_distance_matrix(
+                size,
+                input,
+                layout,
+                LinkageFunction::Average,
+                options,
+            )
+            .err()
+        };
+        let square = DistanceMatrixLayout::Square;
+
+        assert_eq!(
+            reject(3, &[1.0, 2.0], DistanceMatrixLayout::Condensed, &options),
+            Some(ClusteringError::DimensionMismatch {
+                what: "distance matrix",
+                expected: 3,
+                actual: 2,
+            })
+        );
+        assert!(matches!(
+            reject(2, &[0.0, 1.0, 2.0, 0.0], square, &options),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+        assert!(matches!(
+            reject(2, &[1.0, 1.0, 1.0, 0.0], square, &options),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+        assert!(matches!(
+            reject(2, &[0.0, -1.0, -1.0, 0.0], square, &options),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+        assert_eq!(
+            reject(
+                3,
+                &[1.0, f64::INFINITY, 2.0],
+                DistanceMatrixLayout::Condensed,
+                &options
+            ),
+            Some(ClusteringError::NonFiniteInput {
+                row: 0,
+                col: 2,
+                value: f64::INFINITY,
+            })
+        );
+        let mut mean_value = ClusteringOptions::new();
+        mean_value.ordering = LeafOrdering::MeanValue;
+        assert!(matches!(
+            reject(2, &[1.0], DistanceMatrixLayout::Condensed, &mean_value),
+            Some(ClusteringError::UnsupportedOption(_))
+        ));
+        let mut col_weights = ClusteringOptions::new();
+        col_weights.col_weights = Some(vec![1.0, 2.0]);
+        assert_eq!(
+            reject(2, &[1.0], DistanceMatrixLayout::Condensed, &col_weights),
+            Some(ClusteringError::UnsupportedOption(
+                "column weights need values, not distances".into()
+            ))
+        );
+
+        // observations 0 and 3 cannot be compared, the lower one gives way
+        let with_gap = [1.0, 2.0, f64::NAN, 1.5, 2.5, 3.0];
+        let result = cluster_distance_matrix(
+            4,
+            &with_gap,
+            DistanceMatrixLayout::Condensed,
+            LinkageFunction::Average,
+            &ClusteringOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(result.row_tree.as_ref().unwrap().unplaced, vec![0]);
+        assert_eq!(result.row_order[3], 0);
+
+        let mut drop_incomplete = ClusteringOptions::new();
+        drop_incomplete.nan_policy = NanPolicy::DropIncomplete;
+        let result = cluster_distance_matrix(
+            4,
+            &with_gap,
+            DistanceMatrixLayout::Condensed,
+            LinkageFunction::Average,
+            &drop_incomplete,
+        )
+        .unwrap();
+        assert_eq!(result.row_tree.as_ref().unwrap().unplaced, vec![0, 3]);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // with the `parallel` feature the distance rows and the scans in small
+    // chunks are spread over threads, and must give the serial results
+    #[test]
+    fn parallel_scans_match_serial_test() {
+        let (nrows, ncols) = (40, 3);
+        // small integers, so that many distances tie
+        let mut data: Vec<f64> = pseudo_random_values(nrows * ncols, 99)
+            .into_iter()
+            .map(|value| (value / 2.5).floor())
+            .collect();
+        data[7] = f64::NAN;
+        let data_matrix = MatrixView::new(&data, nrows, ncols);
+        let rows: Vec<usize> = (0..nrows).rev().collect();
+        let distance = DistanceMetric::Manhattan;
+
+        let distances =
+            compute_distance_matrix_for_rows(&data_matrix, &rows, distance)
+                .unwrap();
+        let mut expected_distances = Vec::new();
+        for (position, &i) in rows.iter().enumerate() {
+            for &j in &rows[position + 1..] {
+                expected_distances.push(
+                    distance
+                        .compute_pairwise_complete(
+                            &data_matrix.row(i),
+                            &data_matrix.row(j),
+                        )
+                        .unwrap(),
+                );
+            }
+        }
+        assert_eq!(distances, expected_distances);
+
+        for linkage in [
+            LinkageFunction::Average,
+            LinkageFunction::Complete,
+            LinkageFunction::Weighted,
+            LinkageFunction::Ward,
+            LinkageFunction::Centroid,
+            LinkageFunction::Median,
+        ] {
+            let merges = |scan_length: usize| {
+                let distances = CondensedMatrix::new(distances.clone(), nrows);
+                if linkage.is_reducible() {
+                    engine::nn_chain_linkage_in_chunks(
+                        distances,
+                        linkage,
+                        scan_length,
+                        false,
+                    )
+                    .unwrap()
+                } else {
+                    engine::lance_williams_linkage_in_chunks(
+                        distances,
+                        linkage,
+                        scan_length,
+                    )
+                }
+            };
+
+            let expected = merges(usize::MAX);
+            assert!(
+                expected
+                    .windows(2)
+                    .any(|pair| pair[0].distance == pair[1].distance),
+                "{linkage:?} has no tied merges"
+            );
+            for scan_length in [1, 7] {
+                assert_eq!(merges(scan_length), expected, "{linkage:?}");
+            }
+        }
+    }
 
+    // timings rather than a check, compare builds with and without the
+    // `parallel` feature (and RAYON_NUM_THREADS) through
+    // cargo test --release [--features parallel] clustering_timing_test
+    //     -- --ignored --nocapture
     #[test]
-    fn cluster_with_views_test() {
-        let data: Vec<f64> = vec![
-            1.0, 2.0, 3.0, // row 0
-            2.0, 3.0, 4.0, // row 1
-            3.0, 4.0, 5.0, // row 2
-            8.0, 8.0, 8.0, // row 3
-            1.0, 0.0, 1.0, // row 4
-            0.0, 1.0, 0.0, // row 5
-            6.0, 5.0, 4.0, // row 6
-            9.0, 9.0, 9.0, // row 7
-            2.0, 2.0, 2.0, // row 8
-            5.0, 5.0, 5.0, // row 9
-        ];
+    #[ignore]
+    fn clustering_timing_test() {
+        let (nrows, ncols) = (5000, 20);
+        let data = pseudo_random_values(nrows * ncols, 7);
+        let data_matrix = MatrixView::new(&data, nrows, ncols);
+
+        let start = std::time::Instant::now();
+        let distances = compute_distance_matrix_from_view(
+            &data_matrix,
+            DistanceMetric::Euclidean,
+        )
+        .unwrap();
+        println!("distance matrix: {:?}", start.elapsed());
 
-        let result = super::hierarchical_clustering(
-            10,
-            3,
-            data,
-            super::ClusteringAxis::Row,
-            super::LinkageFunction::Average,
-            super::DistanceMetric::Chebyshev,
+        for linkage in [LinkageFunction::Average, LinkageFunction::Centroid] {
+            let start = std::time::Instant::now();
+            let merges = compute_merges(
+                CondensedMatrix::new(distances.clone(), nrows),
+                linkage,
+            );
+            println!("{linkage:?} merges: {:?}", start.elapsed());
+            assert_eq!(merges.len(), nrows - 1);
+        }
+    }
+
+    #[test]
+    fn distance_kernels_test() {
+        // odd lengths leave a last pair outside the SIMD lanes
+        for length in 0..9 {
+            let left: Vec<f64> =
+                (0..length).map(|k| (k as f64 * 1.7).sin() * 3.0).collect();
+            let right: Vec<f64> =
+                (0..length).map(|k| (k as f64 * 0.3).cos() - 0.5).collect();
+            let pairs = || left.iter().zip(&right);
+
+            let expected = [
+                pairs().map(|(x, y)| (x - y) * (x - y)).sum::<f64>(),
+                pairs().map(|(x, y)| (x - y).abs()).sum(),
+                pairs().map(|(x, y)| x * y).sum(),
+                left.iter().map(|x| x * x).sum(),
+                right.iter().map(|y| y * y).sum(),
+            ];
+            let (dot, left_norm, right_norm) =
+                kernels::dot_and_norms(&left, &right);
+            let actual = [
+                kernels::squared_euclidean(&left, &right),
+                kernels::manhattan(&left, &right),
+                dot,
+                left_norm,
+                right_norm,
+            ];
+            for (actual, expected) in actual.into_iter().zip(expected) {
+                assert!((actual - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn scaling_test() {
+        let data = vec![1.0, 2.0, 3.0, 2.0, 4.0, f64::NAN];
+        let data_matrix = MatrixView::new(&data, 2, 3);
+        let same = |left: &[f64], right: &[f64]| {
+            left.len() == right.len()
+                && left.iter().zip(right).all(|(x, y)| {
+                    (x.is_nan() && y.is_nan()) || (x - y).abs() < 1e-12
+                })
+        };
+
+        // sample standard deviations 1 and sqrt(2)
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        assert!(same(
+            &scale(&data_matrix, Scaling::RowZScore),
+            &[-1.0, 0.0, 1.0, -half, half, f64::NAN]
+        ));
+        assert!(same(
+            &scale(&data_matrix, Scaling::ColumnZScore),
+            &[-half, -half, 0.0, half, half, f64::NAN]
+        ));
+        assert!(same(
+            &scale(&data_matrix, Scaling::RowMinMax),
+            &[0.0, 0.5, 1.0, 0.0, 1.0, f64::NAN]
+        ));
+        assert!(same(
+            &scale(&data_matrix, Scaling::ColumnMinMax),
+            &[0.0, 0.0, 0.0, 1.0, 1.0, f64::NAN]
+        ));
+        // the two 2s share ranks 1 and 2 of 0..=4
+        assert!(same(
+            &scale(&data_matrix, Scaling::Quantile),
+            &[0.0, 0.375, 0.75, 0.375, 1.0, f64::NAN]
+        ));
+
+        // rows without spread, all zeros or constant, become 0 whatever the
+        // scaling, not NaN as for all-zero rows in the former JS scaler
+        let flat = vec![0.0, 0.0, 0.0, 4.0, 4.0, 4.0, 1.0, f64::NAN, 1.0];
+        let flat_matrix = MatrixView::new(&flat, 3, 3);
+        for scaling in [Scaling::RowZScore, Scaling::RowMinMax] {
+            assert!(
+                same(
+                    &scale(&flat_matrix, scaling),
+                    &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, f64::NAN, 0.0]
+                ),
+                "{scaling:?}"
+            );
+        }
+        let flat_columns = flat_matrix.transposed().to_row_major();
+        let flat_columns = MatrixView::new(&flat_columns, 3, 3);
+        for scaling in [Scaling::ColumnZScore, Scaling::ColumnMinMax] {
+            assert!(
+                same(
+                    &scale(&flat_columns, scaling),
+                    &[0.0, 0.0, 0.0, 0.0, 0.0, f64::NAN, 0.0, 0.0, 0.0]
+                ),
+                "{scaling:?}"
+            );
+        }
+
+        let counts = vec![0.0, 1.0, 3.0, 7.0];
+        assert_eq!(
+            log2_transform(&MatrixView::new(&counts, 2, 2)),
+            Ok(vec![0.0, 1.0, 2.0, 3.0])
         );
+        assert!(matches!(
+            log2_transform(&MatrixView::new(&[0.0, -1.0], 1, 2)),
+            Err(ClusteringError::UnsupportedOption(_))
+        ));
 
-        result
+        // the values come back exactly as they were clustered
+        let data: Vec<f64> = vec![
+            1.0, 10.0, 100.0, //
+            3.0, 3.0, 3.0, //
+            2.0, 20.0, 150.0, //
+            50.0, 5.0, 0.0,
+        ];
+        let mut options = ClusteringOptions::new();
+        options.log_transform = true;
+        options.scaling = Scaling::RowZScore;
+        let result = cluster_matrix(
+            4,
+            3,
+            data.clone(),
+            ClusteringAxis::Both,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &options,
+        )
+        .unwrap();
+        let logged = log2_transform(&MatrixView::new(&data, 4, 3)).unwrap();
+        let scaled = scale(&MatrixView::new(&logged, 4, 3), Scaling::RowZScore);
+        // the constant row has no spread and is all zeros
+        assert!(same(&scaled[3..6], &[0.0, 0.0, 0.0]));
+        let expected: Vec<f64> = result
             .row_order
             .iter()
-            .enumerate()
-            .for_each(|(x, y)| println!("{x}, {y}"));
+            .flat_map(|&i| result.col_order.iter().map(move |&j| (i, j)))
+            .map(|(i, j)| scaled[i * 3 + j])
+            .collect();
+        assert!(same(&result.values, &expected));
+        assert_eq!(result.row_order[..2], [0, 2]);
+
+        assert!(matches!(
+            cluster_distance_matrix(
+                2,
+                &[1.0],
+                DistanceMatrixLayout::Condensed,
+                LinkageFunction::Average,
+                &options,
+            ),
+            Err(ClusteringError::UnsupportedOption(_))
+        ));
+    }
+
+    #[test]
+    fn normalization_test() {
+        let close = |left: &[f64], right: &[f64]| {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .zip(right)
+                    .all(|(x, y)| (x - y).abs() < 1e-9 * y.abs().max(1.0))
+        };
+
+        // the worked example of quantile normalisation, with ties
+        let data = vec![
+            5.0, 4.0, 3.0, //
+            2.0, 1.0, 4.0, //
+            3.0, 4.0, 6.0, //
+            4.0, 2.0, 8.0,
+        ];
+        let normalized =
+            normalize(&MatrixView::new(&data, 4, 3), Normalization::Quantile)
+                .unwrap();
+        let (a, b, c) = (17.0 / 3.0, 31.0 / 6.0, 14.0 / 3.0);
+        assert!(close(
+            &normalized,
+            &[a, b, 2.0, 2.0, 2.0, 3.0, 3.0, b, c, c, 3.0, a]
+        ));
+
+        // expected factors from the R sources of DESeq2 and edgeR
+        let counts = vec![
+            10.0, 20.0, 12.0, //
+            0.0, 5.0, 3.0, //
+            100.0, 180.0, 130.0, //
+            50.0, 90.0, 40.0, //
+            7.0, 16.0, 9.0, //
+            300.0, 500.0, 380.0,
+        ];
+        let count_matrix = MatrixView::new(&counts, 6, 3);
+        assert!(close(
+            &median_of_ratios_size_factors(&count_matrix).unwrap(),
+            &[0.7532307606201086, 1.4938015821857216, 0.8976127208828634]
+        ));
+        assert!(close(
+            &tmm_factors(&count_matrix).unwrap(),
+            &[0.9765482068913484, 1.0180273258386439, 1.0058816317002903]
+        ));
+        let cpm = normalize(&count_matrix, Normalization::Tmm).unwrap();
+        assert!(close(
+            &cpm[..3],
+            &[21927.5157950762, 24224.21464320253, 20783.681385661093]
+        ));
+
+        // a sample sequenced twice as deep normalises to the same profile
+        let deeper: Vec<f64> = counts
+            .chunks(3)
+            .flat_map(|row| [row[0], 2.0 * row[0]])
+            .collect();
+        for normalization in [Normalization::MedianOfRatios, Normalization::Tmm]
+        {
+            let normalized =
+                normalize(&MatrixView::new(&deeper, 6, 2), normalization)
+                    .unwrap();
+            for row in normalized.chunks(2) {
+                assert!((row[0] - row[1]).abs() < 1e-9 * row[0].max(1.0));
+            }
+        }
+
+        assert!(matches!(
+            normalize(&MatrixView::new(&[1.0, -2.0], 1, 2), Normalization::Tmm),
+            Err(ClusteringError::UnsupportedOption(_))
+        ));
+        assert!(matches!(
+            median_of_ratios_size_factors(&MatrixView::new(
+                &[0.0, 1.0, 2.0, 0.0],
+                2,
+                2
+            )),
+            Err(ClusteringError::UnsupportedOption(_))
+        ));
+
+        // normalised before clustering, and returned normalised
+        let mut options = ClusteringOptions::new();
+        options.normalization = Normalization::MedianOfRatios;
+        let result = cluster_matrix(
+            6,
+            2,
+            deeper.clone(),
+            ClusteringAxis::Column,
+            LinkageFunction::Average,
+            DistanceKind::Euclidean,
+            &options,
+        )
+        .unwrap();
+        let height = result.col_tree.as_ref().unwrap().tree.linkage_matrix()[2];
+        assert!(height < 1e-9);
+        assert!(close(
+            &result.values,
+            &normalize(
+                &MatrixView::new(&deeper, 6, 2),
+                Normalization::MedianOfRatios
+            )
+            .unwrap()
+        ));
     }
 
     #[test]
@@ -372,6 +3352,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn empty_triangular_view_test() {
+        // no rows, no distances
+        let upper = MatrixView::new_upper_triangular(&[], 0, 0);
+        let lower = MatrixView::new_lower_triangular(&[], 0, 0);
+
+        assert!(upper.to_row_major().is_empty());
+        assert!(lower.to_row_major().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "only strided views can be permuted")]
+    fn permuted_triangular_view_test() {
+        let trig_data: Vec<f64> = vec![1.0, 2.0, 3.0];
+        let trig_view = MatrixView::new_upper_triangular(&trig_data, 3, 3);
+
+        trig_view.row_permutation(&[2, 0, 1]);
+    }
+
+    #[test]
+    fn matrix_view_layouts_test() {
+        let data: Vec<f64> = vec![
+            1.0, 2.0, 3.0, // row 0
+            4.0, 5.0, 6.0, // row 1
+        ];
+        let matrix_view = MatrixView::new(&data, 2, 3);
+        let transposed_view = matrix_view.transposed();
+
+        // rows of row-major data are borrowed, columns are gathered
+        assert_eq!(matrix_view.row_slice(1), Some(&data[3..6]));
+        assert!(matches!(matrix_view.row(1), Cow::Borrowed(_)));
+        assert_eq!(transposed_view.row_slice(0), None);
+        assert_eq!(transposed_view.row(2).as_ref(), &[3.0, 6.0]);
+        assert_eq!(transposed_view.transposed().row_slice(0), Some(&data[..3]));
+        assert_eq!(
+            transposed_view.to_row_major(),
+            [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]
+        );
+
+        let (rows, cols) = ([1, 0], [2, 0]);
+        let permuted_view = matrix_view.permutation(&rows, &cols);
+        assert_eq!((permuted_view.nrows(), permuted_view.ncols()), (2, 2));
+        assert_eq!(permuted_view.to_row_major(), [6.0, 4.0, 3.0, 1.0]);
+        assert_eq!(
+            permuted_view.transposed().to_row_major(),
+            [6.0, 3.0, 4.0, 1.0]
+        );
+        assert_eq!(
+            transposed_view.permutation(&cols, &rows).to_row_major(),
+            [6.0, 3.0, 4.0, 1.0]
+        );
+        assert_eq!(
+            matrix_view.col_permutation(&cols).to_row_major(),
+            [3.0, 1.0, 6.0, 4.0]
+        );
+        assert_eq!(
+            matrix_view.row_permutation(&rows).to_row_major(),
+            [4.0, 5.0, 6.0, 1.0, 2.0, 3.0]
+        );
+
+        // the lower triangle 1; 2 3 read back through its transpose
+        let trig_data = vec![1.0, 2.0, 3.0];
+        let lower_view = MatrixView::new_lower_triangular(&trig_data, 3, 3);
+        assert_eq!(
+            lower_view.to_row_major(),
+            [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 2.0, 3.0, 0.0]
+        );
+        assert_eq!(
+            lower_view.transposed().to_row_major(),
+            [0.0, 1.0, 2.0, 0.0, 0.0, 3.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(lower_view[(0, 2)], 0.0);
+        assert_eq!(lower_view.transposed()[(0, 2)], 2.0);
+    }
+
+    #[allow(clippy::vec_init_then_push)]
     fn create_test_tree_for_ladderized_traversal() -> HcTree {
         let mut nodes = Vec::new();
 
@@ -381,35 +3437,40 @@ mod tests {
             id: 0,
             parent: None,
             children: vec![],
-            indices: vec![0],
+            size: 1,
+            height: 0.0,
         });
         // Node 1 (L1) - Subtree size: 1
         nodes.push(Node {
             id: 1,
             parent: None,
             children: vec![],
-            indices: vec![1],
+            size: 1,
+            height: 0.0,
         });
         // Node 2 (L2) - Subtree size: 1
         nodes.push(Node {
             id: 2,
             parent: None,
             children: vec![],
-            indices: vec![2],
+            size: 1,
+            height: 0.0,
         });
         // Node 3 (L3) - Subtree size: 1
         nodes.push(Node {
             id: 3,
             parent: None,
             children: vec![],
-            indices: vec![3],
+            size: 1,
+            height: 0.0,
         });
         // Node 4 (L4) - Subtree size: 1
         nodes.push(Node {
             id: 4,
             parent: None,
             children: vec![],
-            indices: vec![4],
+            size: 1,
+            height: 0.0,
         });
 
         // Internal node N3 (id 5), parent of L1(id 1) and L2(id 2)
@@ -419,7 +3480,8 @@ mod tests {
             id: 5, // N3
             parent: None,
             children: vec![1, 2], // L1, L2
-            indices: vec![1, 2],
+            size: 2,
+            height: 1.0,
         });
         nodes[1].parent = Some(5);
         nodes[2].parent = Some(5);
@@ -431,7 +3493,8 @@ mod tests {
             id: 6, // N2
             parent: None,
             children: vec![3, 4], // L3, L4
-            indices: vec![3, 4],
+            size: 2,
+            height: 2.0,
         });
         nodes[3].parent = Some(6);
         nodes[4].parent = Some(6);
@@ -443,7 +3506,8 @@ mod tests {
             id: 7, // N1
             parent: None,
             children: vec![0, 5], // L0 (smaller), N3 (larger)
-            indices: vec![0, 1, 2],
+            size: 3,
+            height: 3.0,
         });
         nodes[0].parent = Some(7);
         nodes[5].parent = Some(7);
@@ -455,7 +3519,8 @@ mod tests {
             id: 8, // R
             parent: None,
             children: vec![6, 7], // N2 (smaller), N1 (larger)
-            indices: vec![0, 1, 2, 3, 4],
+            size: 5,
+            height: 4.0,
         });
         nodes[6].parent = Some(8); // N2's parent is R
         nodes[7].parent = Some(8); // N1's parent is R
@@ -479,4 +3544,48 @@ mod tests {
             "Ladderized preorder traversal order mismatch for binary tree"
         );
     }
+
+    #[test]
+    fn chained_tree_test() {
+        // every merge adds one leaf to the cluster of all earlier ones, far
+        // deeper than the stack would allow recursing into
+        let size = 100_000;
+        let merges: Vec<Merge> = (1..size)
+            .map(|leaf| Merge {
+                first: if leaf == 1 { 0 } else { size + leaf - 2 },
+                second: leaf,
+                distance: leaf as f64,
+            })
+            .collect();
+        let mut tree = HcTree::from_merges(size, &merges);
+
+        let root = tree.root().unwrap();
+        assert_eq!(root.size, size);
+        assert_eq!(tree.nodes[size].size, 2);
+
+        let spans = tree.leaf_spans();
+        assert_eq!(spans.leaves(root.id).len(), size);
+        assert_eq!(spans.leaves(size + 1), &[0, 1, 2]);
+        assert!(spans.contains(root.id, size - 1));
+        assert!(!spans.contains(size + 1, 3));
+
+        let ladderized: Vec<usize> = tree
+            .ladderized_preorder_node_view()
+            .filter(|node| node.children.is_empty())
+            .map(|node| node.id)
+            .collect();
+
+        tree.ladderize();
+        let leaves: Vec<usize> =
+            tree.preorder_leaf_traversal().map(|node| node.id).collect();
+        assert_eq!(leaves, ladderized);
+        assert_eq!(&leaves[..3], &[0, 1, 2]);
+        assert_eq!(leaves[size - 1], size - 1);
+
+        tree.ladderize_ascending();
+        tree.reorder_by_weights(&vec![1.0; size]);
+        let leaves: Vec<usize> =
+            tree.preorder_leaf_traversal().map(|node| node.id).collect();
+        assert_eq!(&leaves[..2], &[size - 1, size - 2]);
+    }
 }
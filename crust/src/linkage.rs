@@ -1,115 +1,268 @@
 use wasm_bindgen::prelude::*;
 
-use crate::{
-    distance::{Distance, Euclidean},
-    tree::Node,
-    utils::{MatrixLike, MatrixView},
-};
-
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy)]
 pub enum LinkageFunction {
     Average,
     Ward,
+    Single,
+    Complete,
+    Weighted,
+    Centroid,
+    Median,
 }
 
 impl LinkageFunction {
-    pub fn compute_from_views(
+    /// Distance between cluster `k` and the union of clusters `i` and `j`,
+    /// expressed through the distances before the merge (Lance–Williams).
+    pub fn update(
         &self,
-        first_node: &Node,
-        second_node: &Node,
-        distance_matrix: &MatrixView,
-        data_matrix: &MatrixView,
+        distance_ki: f64,
+        distance_kj: f64,
+        distance_ij: f64,
+        sizes: ClusterSizes,
     ) -> f64 {
         match self {
-            LinkageFunction::Average => AverageLinkage::compute_from_views(
-                first_node,
-                second_node,
-                distance_matrix,
-                data_matrix,
+            LinkageFunction::Average => AverageLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
+            ),
+            LinkageFunction::Ward => WardLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
+            ),
+            LinkageFunction::Single => SingleLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
+            ),
+            LinkageFunction::Complete => CompleteLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
             ),
-            LinkageFunction::Ward => WardLinkage::compute_from_views(
-                first_node,
-                second_node,
-                distance_matrix,
-                data_matrix,
+            LinkageFunction::Weighted => WeightedLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
             ),
+            LinkageFunction::Centroid => CentroidLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
+            ),
+            LinkageFunction::Median => MedianLinkage::update(
+                distance_ki,
+                distance_kj,
+                distance_ij,
+                sizes,
+            ),
+        }
+    }
+
+    /// Whether merging two clusters can never bring the result closer to a
+    /// third cluster than either part was, which nearest-neighbour chain
+    /// clustering relies on.
+    pub fn is_reducible(&self) -> bool {
+        match self {
+            LinkageFunction::Average
+            | LinkageFunction::Ward
+            | LinkageFunction::Single
+            | LinkageFunction::Complete
+            | LinkageFunction::Weighted => true,
+            LinkageFunction::Centroid | LinkageFunction::Median => false,
+        }
+    }
+
+    /// Maps an input dissimilarity into the space the update rule works in.
+    pub fn prepare(&self, distance: f64) -> f64 {
+        match self {
+            LinkageFunction::Average => AverageLinkage::prepare(distance),
+            LinkageFunction::Ward => WardLinkage::prepare(distance),
+            LinkageFunction::Single => SingleLinkage::prepare(distance),
+            LinkageFunction::Complete => CompleteLinkage::prepare(distance),
+            LinkageFunction::Weighted => WeightedLinkage::prepare(distance),
+            LinkageFunction::Centroid => CentroidLinkage::prepare(distance),
+            LinkageFunction::Median => MedianLinkage::prepare(distance),
+        }
+    }
+
+    /// Maps a working dissimilarity back to a merge height.
+    pub fn finalize(&self, distance: f64) -> f64 {
+        match self {
+            LinkageFunction::Average => AverageLinkage::finalize(distance),
+            LinkageFunction::Ward => WardLinkage::finalize(distance),
+            LinkageFunction::Single => SingleLinkage::finalize(distance),
+            LinkageFunction::Complete => CompleteLinkage::finalize(distance),
+            LinkageFunction::Weighted => WeightedLinkage::finalize(distance),
+            LinkageFunction::Centroid => CentroidLinkage::finalize(distance),
+            LinkageFunction::Median => MedianLinkage::finalize(distance),
         }
     }
 }
 
+/// Number of observations in the two clusters being merged (`i`, `j`) and
+/// in the cluster whose distance is being updated (`k`).
+#[derive(Debug, Clone, Copy)]
+pub struct ClusterSizes {
+    pub i: f64,
+    pub j: f64,
+    pub k: f64,
+}
+
 pub trait Linkage {
-    fn compute_from_views(
-        first_node: &Node,
-        second_node: &Node,
-        distance_matrix: &MatrixView,
-        data_matrix: &MatrixView,
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        distance_ij: f64,
+        sizes: ClusterSizes,
     ) -> f64;
+
+    fn prepare(distance: f64) -> f64 {
+        distance
+    }
+
+    fn finalize(distance: f64) -> f64 {
+        distance
+    }
 }
 
 pub struct AverageLinkage;
 
 impl Linkage for AverageLinkage {
-    fn compute_from_views(
-        first_node: &Node,
-        second_node: &Node,
-        distance_matrix: &MatrixView,
-        _data_matrix: &MatrixView,
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        _distance_ij: f64,
+        sizes: ClusterSizes,
     ) -> f64 {
-        let mut sum = 0.0;
-
-        for &i in &first_node.indices {
-            for &j in &second_node.indices {
-                if i == j {
-                    continue;
-                }
-                // indexing works with upper triangular data matrix i must be < j
-                let (i, j) = if i > j { (j, i) } else { (i, j) };
-                // let idx = i * n - (i * (i + 1) / 2) + (j - i - 1);
-                sum += distance_matrix.get(i, j);
-            }
-        }
-
-        sum / (first_node.indices.len() as f64)
-            / (second_node.indices.len() as f64)
+        (sizes.i * distance_ki + sizes.j * distance_kj) / (sizes.i + sizes.j)
     }
 }
 
+/// Ward's minimum variance method: merges the pair whose union increases
+/// the within-cluster sum of squares the least. Heights are
+/// sqrt(2 * nᵢ * nⱼ / (nᵢ + nⱼ)) times the distance between the centroids,
+/// as in SciPy and R's `hclust(method = "ward.D2")`.
 pub struct WardLinkage;
 
 impl Linkage for WardLinkage {
-    fn compute_from_views(
-        first_node: &Node,
-        second_node: &Node,
-        _distance_matrix: &MatrixView,
-        data_matrix: &MatrixView,
+    // works on squared distances, heights are reported as their square root
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        distance_ij: f64,
+        sizes: ClusterSizes,
     ) -> f64 {
-        let first_centroid_node_count = first_node.indices.len() as f64;
-        let mut first_centroid = vec![0.0; data_matrix.ncols()];
-
-        for col_idx in 0..data_matrix.ncols() {
-            for row_idx in first_node.indices.iter() {
-                first_centroid[col_idx] += data_matrix.get(*row_idx, col_idx);
-            }
-            first_centroid[col_idx] /= first_centroid_node_count;
-        }
+        ((sizes.i + sizes.k) * distance_ki + (sizes.j + sizes.k) * distance_kj
+            - sizes.k * distance_ij)
+            / (sizes.i + sizes.j + sizes.k)
+    }
 
-        let second_centroid_node_count = second_node.indices.len() as f64;
-        let mut second_centroid = vec![0.0; data_matrix.ncols()];
+    fn prepare(distance: f64) -> f64 {
+        distance.powi(2)
+    }
 
-        for col_idx in 0..data_matrix.ncols() {
-            for row_idx in second_node.indices.iter() {
-                second_centroid[col_idx] += data_matrix.get(*row_idx, col_idx);
-            }
-            second_centroid[col_idx] /= second_centroid_node_count;
-        }
+    fn finalize(distance: f64) -> f64 {
+        distance.sqrt()
+    }
+}
+
+pub struct SingleLinkage;
+
+impl Linkage for SingleLinkage {
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        _distance_ij: f64,
+        _sizes: ClusterSizes,
+    ) -> f64 {
+        distance_ki.min(distance_kj)
+    }
+}
+
+pub struct CompleteLinkage;
+
+impl Linkage for CompleteLinkage {
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        _distance_ij: f64,
+        _sizes: ClusterSizes,
+    ) -> f64 {
+        distance_ki.max(distance_kj)
+    }
+}
+
+/// WPGMA: both merged clusters weigh the same regardless of their size.
+pub struct WeightedLinkage;
+
+impl Linkage for WeightedLinkage {
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        _distance_ij: f64,
+        _sizes: ClusterSizes,
+    ) -> f64 {
+        (distance_ki + distance_kj) / 2.0
+    }
+}
 
-        let centroid_distance =
-            Euclidean.compute(&first_centroid, &second_centroid);
+/// UPGMC: distance between cluster centroids. Merge heights are not
+/// guaranteed to increase, so this linkage is not reducible.
+pub struct CentroidLinkage;
+
+impl Linkage for CentroidLinkage {
+    // works on squared distances like `WardLinkage`
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        distance_ij: f64,
+        sizes: ClusterSizes,
+    ) -> f64 {
+        let size_ij = sizes.i + sizes.j;
+
+        (sizes.i * distance_ki + sizes.j * distance_kj) / size_ij
+            - sizes.i * sizes.j * distance_ij / (size_ij * size_ij)
+    }
+
+    fn prepare(distance: f64) -> f64 {
+        distance.powi(2)
+    }
+
+    fn finalize(distance: f64) -> f64 {
+        distance.sqrt()
+    }
+}
+
+/// WPGMC: centroid linkage where the merged centroid is the midpoint of the
+/// two parts, regardless of their size.
+pub struct MedianLinkage;
+
+impl Linkage for MedianLinkage {
+    // works on squared distances like `WardLinkage`
+    fn update(
+        distance_ki: f64,
+        distance_kj: f64,
+        distance_ij: f64,
+        _sizes: ClusterSizes,
+    ) -> f64 {
+        (distance_ki + distance_kj) / 2.0 - distance_ij / 4.0
+    }
+
+    fn prepare(distance: f64) -> f64 {
+        distance.powi(2)
+    }
 
-        (((first_centroid_node_count + second_centroid_node_count)
-            / (first_centroid_node_count * second_centroid_node_count))
-            * centroid_distance.powi(2))
-        .sqrt()
+    fn finalize(distance: f64) -> f64 {
+        distance.sqrt()
     }
 }
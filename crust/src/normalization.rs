@@ -0,0 +1,335 @@
+use wasm_bindgen::prelude::*;
+
+use crate::distance::ranks;
+use crate::error::ClusteringError;
+use crate::utils::{MatrixLike, MatrixView};
+
+/// Sample-level normalisation of an expression matrix with genes in rows
+/// and samples in columns, applied before `log_transform` and `scaling`.
+/// It removes differences between samples that come from sequencing depth
+/// rather than biology. NaN stays NaN.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Normalization {
+    #[default]
+    None,
+    /// Every column given the same distribution, the mean of the sorted
+    /// columns, as `normalize.quantiles` of preprocessCore.
+    Quantile,
+    /// Counts divided by DESeq2's median-of-ratios size factors, see
+    /// `median_of_ratios_size_factors`.
+    MedianOfRatios,
+    /// Counts per million of the library sizes corrected by edgeR's TMM
+    /// factors, see `tmm_factors`.
+    Tmm,
+}
+
+/// The values of `data_matrix` normalised by `normalization`, row by row.
+pub fn normalize(
+    data_matrix: &MatrixView,
+    normalization: Normalization,
+) -> Result<Vec<f64>, ClusteringError> {
+    let columns: Vec<Vec<f64>> = (0..data_matrix.ncols())
+        .map(|j| data_matrix.col(j))
+        .collect();
+
+    let columns = match normalization {
+        Normalization::None => columns,
+        Normalization::Quantile => quantile_normalize(&columns),
+        Normalization::MedianOfRatios => {
+            let factors = median_of_ratios_size_factors(data_matrix)?;
+            columns
+                .iter()
+                .zip(factors)
+                .map(|(column, factor)| {
+                    column.iter().map(|value| value / factor).collect()
+                })
+                .collect()
+        }
+        Normalization::Tmm => {
+            let factors = tmm_factors(data_matrix)?;
+            columns
+                .iter()
+                .zip(factors)
+                .map(|(column, factor)| {
+                    let library_size = factor * observed_sum(column);
+                    column
+                        .iter()
+                        .map(|value| value / library_size * 1e6)
+                        .collect()
+                })
+                .collect()
+        }
+    };
+
+    Ok((0..data_matrix.nrows())
+        .flat_map(|i| columns.iter().map(move |column| column[i]))
+        .collect())
+}
+
+/// DESeq2's `estimateSizeFactorsForMatrix`: per column, the median ratio
+/// of its counts to the geometric mean of their row. Rows with a zero or
+/// NaN in any column have no geometric mean and are left out.
+pub fn median_of_ratios_size_factors(
+    data_matrix: &MatrixView,
+) -> Result<Vec<f64>, ClusteringError> {
+    check_counts(data_matrix)?;
+
+    let log_rows: Vec<Vec<f64>> = (0..data_matrix.nrows())
+        .map(|i| data_matrix.row(i))
+        .filter(|row| row.iter().all(|&value| value > 0.0))
+        .map(|row| row.iter().map(|value| value.ln()).collect())
+        .collect();
+    if log_rows.is_empty() {
+        return Err(ClusteringError::UnsupportedOption(
+            "median-of-ratios size factors need a row without zeros".into(),
+        ));
+    }
+    let log_geometric_means: Vec<f64> = log_rows
+        .iter()
+        .map(|row| row.iter().sum::<f64>() / row.len() as f64)
+        .collect();
+
+    Ok((0..data_matrix.ncols())
+        .map(|j| {
+            let mut log_ratios: Vec<f64> = log_rows
+                .iter()
+                .zip(&log_geometric_means)
+                .map(|(row, mean)| row[j] - mean)
+                .collect();
+            median(&mut log_ratios).exp()
+        })
+        .collect())
+}
+
+/// edgeR's `calcNormFactors(method = "TMM")` with its default trimming:
+/// per column, the weighted mean of the log ratios to a reference column
+/// after trimming 30% of the log ratios and 5% of the mean log
+/// expressions at both ends, scaled to a geometric mean of 1. Rows with a
+/// NaN are left out.
+pub fn tmm_factors(
+    data_matrix: &MatrixView,
+) -> Result<Vec<f64>, ClusteringError> {
+    check_counts(data_matrix)?;
+
+    let columns: Vec<Vec<f64>> = {
+        let rows: Vec<_> = (0..data_matrix.nrows())
+            .map(|i| data_matrix.row(i))
+            .filter(|row| {
+                row.iter().all(|value| !value.is_nan())
+                    && row.iter().any(|&value| value > 0.0)
+            })
+            .collect();
+        (0..data_matrix.ncols())
+            .map(|j| rows.iter().map(|row| row[j]).collect())
+            .collect()
+    };
+    if columns.first().is_none_or(Vec::is_empty) {
+        return Ok(vec![1.0; data_matrix.ncols()]);
+    }
+    let library_sizes: Vec<f64> =
+        columns.iter().map(|column| column.iter().sum()).collect();
+
+    let upper_quartiles: Vec<f64> = columns
+        .iter()
+        .zip(&library_sizes)
+        .map(|(column, library_size)| {
+            let mut proportions: Vec<f64> =
+                column.iter().map(|value| value / library_size).collect();
+            proportions.sort_by(f64::total_cmp);
+            quantile(&proportions, 0.75)
+        })
+        .collect();
+    let mut sorted_quartiles = upper_quartiles.clone();
+    sorted_quartiles.sort_by(f64::total_cmp);
+    let reference = if median(&mut sorted_quartiles) < 1e-20 {
+        // first column with the largest sum of square roots
+        let root_sums: Vec<f64> = columns
+            .iter()
+            .map(|column| column.iter().map(|value| value.sqrt()).sum())
+            .collect();
+        first_extreme(&root_sums, |sum| -sum)
+    } else {
+        let mean =
+            upper_quartiles.iter().sum::<f64>() / upper_quartiles.len() as f64;
+        first_extreme(&upper_quartiles, |quartile| (quartile - mean).abs())
+    };
+
+    let factors: Vec<f64> = columns
+        .iter()
+        .zip(&library_sizes)
+        .map(|(column, &library_size)| {
+            tmm_factor(
+                column,
+                &columns[reference],
+                library_size,
+                library_sizes[reference],
+            )
+        })
+        .collect();
+    let log_mean = factors.iter().map(|factor| factor.ln()).sum::<f64>()
+        / factors.len() as f64;
+
+    Ok(factors
+        .into_iter()
+        .map(|factor| factor / log_mean.exp())
+        .collect())
+}
+
+/// edgeR's `.calcFactorTMM` of `observed` against `reference`.
+fn tmm_factor(
+    observed: &[f64],
+    reference: &[f64],
+    observed_size: f64,
+    reference_size: f64,
+) -> f64 {
+    let (mut log_ratios, mut abundances, mut variances) =
+        (Vec::new(), Vec::new(), Vec::new());
+    for (&x, &y) in observed.iter().zip(reference) {
+        let log_ratio = ((x / observed_size) / (y / reference_size)).log2();
+        let abundance =
+            ((x / observed_size).log2() + (y / reference_size).log2()) / 2.0;
+        if log_ratio.is_finite() && abundance.is_finite() {
+            log_ratios.push(log_ratio);
+            abundances.push(abundance);
+            variances.push(
+                (observed_size - x) / observed_size / x
+                    + (reference_size - y) / reference_size / y,
+            );
+        }
+    }
+    if log_ratios.iter().all(|log_ratio| log_ratio.abs() < 1e-6) {
+        return 1.0;
+    }
+
+    let n = log_ratios.len();
+    let ratio_low = (n as f64 * 0.3).floor() + 1.0;
+    let ratio_high = n as f64 + 1.0 - ratio_low;
+    let abundance_low = (n as f64 * 0.05).floor() + 1.0;
+    let abundance_high = n as f64 + 1.0 - abundance_low;
+
+    let (weighted_sum, weights) = ranks(&log_ratios)
+        .into_iter()
+        .zip(ranks(&abundances))
+        .enumerate()
+        .filter(|(_, (ratio_rank, abundance_rank))| {
+            (ratio_low..=ratio_high).contains(ratio_rank)
+                && (abundance_low..=abundance_high).contains(abundance_rank)
+        })
+        .fold((0.0, 0.0), |(weighted_sum, weights), (k, _)| {
+            (
+                weighted_sum + log_ratios[k] / variances[k],
+                weights + 1.0 / variances[k],
+            )
+        });
+    let log_factor = weighted_sum / weights;
+
+    if log_factor.is_nan() {
+        1.0
+    } else {
+        log_factor.exp2()
+    }
+}
+
+/// Every column replaced by the mean distribution of all columns. Tied
+/// values get the mean of the reference values their ranks span. A column
+/// with NaN keeps it and spreads its observed values over the whole
+/// reference distribution, as preprocessCore does.
+fn quantile_normalize(columns: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let nrows = columns.first().map_or(0, Vec::len);
+    let sorted: Vec<Vec<f64>> = columns
+        .iter()
+        .map(|column| {
+            let mut observed: Vec<f64> = column
+                .iter()
+                .copied()
+                .filter(|value| !value.is_nan())
+                .collect();
+            observed.sort_by(f64::total_cmp);
+            observed
+        })
+        .filter(|observed| !observed.is_empty())
+        .collect();
+
+    let last = nrows.saturating_sub(1).max(1) as f64;
+    let reference: Vec<f64> = (0..nrows)
+        .map(|k| {
+            sorted
+                .iter()
+                .map(|observed| quantile(observed, k as f64 / last))
+                .sum::<f64>()
+                / sorted.len() as f64
+        })
+        .collect();
+
+    columns
+        .iter()
+        .map(|column| {
+            let mut observed: Vec<usize> =
+                (0..column.len()).filter(|&i| !column[i].is_nan()).collect();
+            observed.sort_by(|&a, &b| column[a].total_cmp(&column[b]));
+            let last = observed.len().saturating_sub(1).max(1) as f64;
+
+            let mut normalized = column.clone();
+            let mut start = 0;
+            while start < observed.len() {
+                let value = column[observed[start]];
+                let end = start
+                    + observed[start..]
+                        .iter()
+                        .take_while(|&&i| column[i] == value)
+                        .count();
+                let mean = (start..end)
+                    .map(|rank| quantile(&reference, rank as f64 / last))
+                    .sum::<f64>()
+                    / (end - start) as f64;
+                for &i in &observed[start..end] {
+                    normalized[i] = mean;
+                }
+                start = end;
+            }
+            normalized
+        })
+        .collect()
+}
+
+/// Rejects negative values, which are no counts.
+fn check_counts(data_matrix: &MatrixView) -> Result<(), ClusteringError> {
+    for i in 0..data_matrix.nrows() {
+        if let Some(j) =
+            data_matrix.row(i).iter().position(|&value| value < 0.0)
+        {
+            return Err(ClusteringError::UnsupportedOption(format!(
+                "count normalisation needs non-negative values, got {} at \
+                 row {i}, column {j}",
+                data_matrix.get(i, j)
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Sample quantile of sorted values, interpolated linearly between order
+/// statistics like R's default `quantile(type = 7)`.
+fn quantile(sorted: &[f64], p: f64) -> f64 {
+    let position = p * sorted.len().saturating_sub(1) as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (position - below as f64) * (sorted[above] - sorted[below])
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    quantile(values, 0.5)
+}
+
+fn observed_sum(values: &[f64]) -> f64 {
+    values.iter().filter(|value| !value.is_nan()).sum()
+}
+
+/// Index of the first smallest `key`, like R's `which.min`.
+fn first_extreme(values: &[f64], key: impl Fn(f64) -> f64) -> usize {
+    (0..values.len())
+        .min_by(|&a, &b| key(values[a]).total_cmp(&key(values[b])))
+        .unwrap_or(0)
+}
@@ -0,0 +1,163 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    tree::{HcTree, LeafSpans},
+    utils::CondensedMatrix,
+};
+
+/// How the two children of every merge are arranged, which fixes the leaf
+/// order shown along a heatmap axis.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LeafOrdering {
+    /// Larger subtrees first, the lower node id on ties, see
+    /// `HcTree::ladderize`.
+    #[default]
+    LadderizeDescending,
+    /// Smaller subtrees first.
+    LadderizeAscending,
+    /// Children in the order they were merged, the lower node id first:
+    /// the leaf order of SciPy's `leaves_list`.
+    MergeOrder,
+    /// Subtrees with the lower mean value first, the default of R's
+    /// `heatmap`.
+    MeanValue,
+    /// Subtrees with the lower sum of user supplied leaf weights first,
+    /// see `HcTree::reorder_by_weights`.
+    Weights,
+    /// Smallest sum of distances between neighbouring leaves, like SciPy's
+    /// `optimal_leaf_ordering`, for at most `MAX_OPTIMAL_LEAVES` leaves.
+    Optimal,
+}
+
+/// Largest tree `optimal_leaf_ordering` is run on: its cost table then
+/// takes 8 MB and the search up to 10⁹ steps, a few seconds in the worker.
+pub const MAX_OPTIMAL_LEAVES: usize = 1000;
+
+/// Flips the children of `tree` so that the sum of `distances` between
+/// adjacent leaves is minimal (Bar-Joseph, Gifford and Jaakkola, 2001).
+///
+/// Runs in O(n³) time and keeps an n × n cost table of f64: 5,000 leaves
+/// would take 200 MB and minutes, which is why `build_tree` refuses trees
+/// larger than `MAX_OPTIMAL_LEAVES`.
+pub fn optimal_leaf_ordering(tree: &mut HcTree, distances: &CondensedMatrix) {
+    let number_of_leaves = distances.size();
+
+    // cost[i * n + j]: cheapest ordering of the subtree under the last common
+    // ancestor of leaves `i` and `j` that starts with `i` and ends with `j`
+    let mut cost: Vec<f64> = vec![0.0; number_of_leaves * number_of_leaves];
+    // cheapest way from the current `i` to each leaf right of the split
+    let mut through: Vec<f64> = vec![f64::INFINITY; number_of_leaves];
+    // flipping children below keeps every node's set of leaves
+    let spans = tree.leaf_spans();
+
+    // children always have smaller ids than their parent
+    for node in &tree.nodes {
+        if node.children.is_empty() {
+            continue;
+        }
+        let &[left, right] = node.children.as_slice() else {
+            panic!("optimal leaf ordering needs a binary tree");
+        };
+
+        for &first in spans.leaves(left) {
+            let first_ends = opposite_ends(tree, &spans, left, first);
+
+            for &middle in spans.leaves(right) {
+                through[middle] = first_ends
+                    .iter()
+                    .map(|&end| {
+                        cost[first * number_of_leaves + end]
+                            + distances.get(end, middle)
+                    })
+                    .fold(f64::INFINITY, f64::min);
+            }
+
+            for &last in spans.leaves(right) {
+                let best = opposite_ends(tree, &spans, right, last)
+                    .iter()
+                    .map(|&start| {
+                        through[start] + cost[start * number_of_leaves + last]
+                    })
+                    .fold(f64::INFINITY, f64::min);
+
+                cost[first * number_of_leaves + last] = best;
+                cost[last * number_of_leaves + first] = best;
+            }
+        }
+    }
+
+    let Some(root_id) = tree.root().map(|root| root.id) else {
+        return;
+    };
+
+    let mut endpoints: Option<(usize, usize, f64)> = None;
+    for &first in spans.leaves(root_id) {
+        for &last in opposite_ends(tree, &spans, root_id, first) {
+            let candidate = cost[first * number_of_leaves + last];
+            if endpoints.is_none_or(|(_, _, best)| candidate < best) {
+                endpoints = Some((first, last, candidate));
+            }
+        }
+    }
+
+    let Some((first, last, _)) = endpoints else {
+        return;
+    };
+
+    // walk down again, putting the child holding `first` on the left and
+    // recovering the leaves on both sides of each split
+    let mut stack: Vec<(usize, usize, usize)> = vec![(root_id, first, last)];
+
+    while let Some((node_id, first, last)) = stack.pop() {
+        let children = &tree.nodes[node_id].children;
+        if children.is_empty() {
+            continue;
+        }
+
+        let (left, right) = if spans.contains(children[0], first) {
+            (children[0], children[1])
+        } else {
+            (children[1], children[0])
+        };
+
+        let mut split: Option<(usize, usize, f64)> = None;
+        for &end in opposite_ends(tree, &spans, left, first) {
+            for &start in opposite_ends(tree, &spans, right, last) {
+                let candidate = cost[first * number_of_leaves + end]
+                    + distances.get(end, start)
+                    + cost[start * number_of_leaves + last];
+
+                if split.is_none_or(|(_, _, best)| candidate < best) {
+                    split = Some((end, start, candidate));
+                }
+            }
+        }
+        let (end, start, _) = split.expect("both children have leaves");
+
+        tree.nodes[node_id].children = vec![left, right];
+        stack.push((left, first, end));
+        stack.push((right, start, last));
+    }
+}
+
+/// Leaves that can sit at the other end of `node_id`'s subtree when `leaf`
+/// is at one end: those of the child not holding `leaf`, or `leaf` itself.
+fn opposite_ends<'a>(
+    tree: &HcTree,
+    spans: &'a LeafSpans,
+    node_id: usize,
+    leaf: usize,
+) -> &'a [usize] {
+    match tree.nodes[node_id].children.as_slice() {
+        [] => spans.leaves(node_id),
+        [first, second] => {
+            if spans.contains(*first, leaf) {
+                spans.leaves(*second)
+            } else {
+                spans.leaves(*first)
+            }
+        }
+        _ => panic!("optimal leaf ordering needs a binary tree"),
+    }
+}
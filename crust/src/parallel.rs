@@ -0,0 +1,106 @@
+//! Iterators that spread their items over the rayon thread pool with the
+//! `parallel` feature and run on the calling thread without it. Both kinds
+//! offer the adapters used in this crate (`map`, `filter_map`, `enumerate`,
+//! `min_by`, `for_each`, `try_for_each`), so callers are written once and
+//! import `rayon::prelude::*` under the feature. `reduce` covers the one
+//! adapter whose name differs between the two.
+
+use std::ops::Range;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// `0..n` cut into consecutive runs of `chunk_len` indices (the last one
+/// shorter), each handed to a thread as a whole so that cheap per-index
+/// work runs as a plain loop.
+#[cfg(feature = "parallel")]
+pub fn chunks(
+    n: usize,
+    chunk_len: usize,
+) -> impl IndexedParallelIterator<Item = Range<usize>> {
+    (0..n.div_ceil(chunk_len))
+        .into_par_iter()
+        .map(move |k| k * chunk_len..n.min((k + 1) * chunk_len))
+}
+
+/// `0..n` cut into consecutive runs of `chunk_len` indices (the last one
+/// shorter).
+#[cfg(not(feature = "parallel"))]
+pub fn chunks(
+    n: usize,
+    chunk_len: usize,
+) -> impl Iterator<Item = Range<usize>> {
+    (0..n.div_ceil(chunk_len))
+        .map(move |k| k * chunk_len..n.min((k + 1) * chunk_len))
+}
+
+/// `items` cut into consecutive runs of `chunk_len` elements, each with the
+/// index of its first element, to be updated in place.
+#[cfg(feature = "parallel")]
+pub fn chunks_mut<T: Send>(
+    items: &mut [T],
+    chunk_len: usize,
+) -> impl IndexedParallelIterator<Item = (usize, &mut [T])> {
+    items
+        .par_chunks_mut(chunk_len)
+        .enumerate()
+        .map(move |(k, chunk)| (k * chunk_len, chunk))
+}
+
+/// `items` cut into consecutive runs of `chunk_len` elements, each with the
+/// index of its first element, to be updated in place.
+#[cfg(not(feature = "parallel"))]
+pub fn chunks_mut<T>(
+    items: &mut [T],
+    chunk_len: usize,
+) -> impl Iterator<Item = (usize, &mut [T])> {
+    items
+        .chunks_mut(chunk_len)
+        .enumerate()
+        .map(move |(k, chunk)| (k * chunk_len, chunk))
+}
+
+/// The elements of `items`, in order.
+#[cfg(feature = "parallel")]
+pub fn items<T: Send>(items: Vec<T>) -> rayon::vec::IntoIter<T> {
+    items.into_par_iter()
+}
+
+/// The elements of `items`, in order.
+#[cfg(not(feature = "parallel"))]
+pub fn items<T>(items: Vec<T>) -> std::vec::IntoIter<T> {
+    items.into_iter()
+}
+
+/// `items` combined pairwise with the associative `op`, `None` when there
+/// are none.
+#[cfg(feature = "parallel")]
+pub fn reduce<T: Send>(
+    items: impl ParallelIterator<Item = T>,
+    op: impl Fn(T, T) -> T + Sync + Send,
+) -> Option<T> {
+    items.reduce_with(op)
+}
+
+/// `items` combined pairwise with the associative `op`, `None` when there
+/// are none.
+#[cfg(not(feature = "parallel"))]
+pub fn reduce<T>(
+    items: impl Iterator<Item = T>,
+    op: impl Fn(T, T) -> T,
+) -> Option<T> {
+    items.reduce(op)
+}
+
+/// Runs `f` on a thread of the rayon pool, so that the parallel iterators it
+/// starts over and over skip the hand-over from an outside thread.
+#[cfg(feature = "parallel")]
+pub fn in_pool<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    rayon::scope(|_| f())
+}
+
+/// Runs `f` on the calling thread.
+#[cfg(not(feature = "parallel"))]
+pub fn in_pool<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
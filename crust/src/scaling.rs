@@ -0,0 +1,154 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::ClusteringError;
+use crate::utils::{MatrixLike, MatrixView};
+
+/// Rescaling of the values before they are clustered. The result holds the
+/// rescaled values too, so the heatmap shows exactly what was clustered.
+/// NaN stays NaN and is left out of every statistic.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Scaling {
+    #[default]
+    None,
+    /// Every row centred on its mean and divided by its sample standard
+    /// deviation (n - 1 denominator), as R's `scale` does for columns.
+    RowZScore,
+    /// Every column centred and divided like `RowZScore`.
+    ColumnZScore,
+    /// Every row mapped linearly onto [0, 1].
+    RowMinMax,
+    /// Every column mapped linearly onto [0, 1].
+    ColumnMinMax,
+    /// Every value replaced by its rank among all values of the matrix,
+    /// mapped onto [0, 1]. Tied values share their average rank.
+    Quantile,
+}
+
+/// log2(x + 1) of every value, row by row. Values of -1 and below have no
+/// logarithm and are rejected.
+pub fn log2_transform(
+    data_matrix: &MatrixView,
+) -> Result<Vec<f64>, ClusteringError> {
+    let mut values =
+        Vec::with_capacity(data_matrix.nrows() * data_matrix.ncols());
+    for i in 0..data_matrix.nrows() {
+        for (j, &value) in data_matrix.row(i).iter().enumerate() {
+            if value <= -1.0 {
+                return Err(ClusteringError::UnsupportedOption(format!(
+                    "log2(x + 1) needs values above -1, got {value} at row \
+                     {i}, column {j}"
+                )));
+            }
+            values.push((value + 1.0).log2());
+        }
+    }
+    Ok(values)
+}
+
+/// The values of `data_matrix` rescaled by `scaling`, row by row. Rows or
+/// columns without any spread have nothing to rescale and become 0.
+pub fn scale(data_matrix: &MatrixView, scaling: Scaling) -> Vec<f64> {
+    match scaling {
+        Scaling::None => data_matrix.to_row_major(),
+        Scaling::RowZScore => by_rows(data_matrix, z_scores),
+        Scaling::ColumnZScore => by_cols(data_matrix, z_scores),
+        Scaling::RowMinMax => by_rows(data_matrix, min_max),
+        Scaling::ColumnMinMax => by_cols(data_matrix, min_max),
+        Scaling::Quantile => quantiles(&data_matrix.to_row_major()),
+    }
+}
+
+fn by_rows(
+    data_matrix: &MatrixView,
+    f: impl Fn(&[f64]) -> Vec<f64>,
+) -> Vec<f64> {
+    (0..data_matrix.nrows())
+        .flat_map(|i| f(&data_matrix.row(i)))
+        .collect()
+}
+
+fn by_cols(
+    data_matrix: &MatrixView,
+    f: impl Fn(&[f64]) -> Vec<f64>,
+) -> Vec<f64> {
+    let transposed = data_matrix.transposed();
+    let scaled = by_rows(&transposed, f);
+    MatrixView::new(&scaled, transposed.nrows(), transposed.ncols())
+        .transposed()
+        .to_row_major()
+}
+
+fn z_scores(values: &[f64]) -> Vec<f64> {
+    let (sum, count) = values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    let mean = sum / count as f64;
+    let squares: f64 = values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .map(|value| (value - mean).powi(2))
+        .sum();
+    let sd = (squares / (count as f64 - 1.0)).sqrt();
+
+    values
+        .iter()
+        .map(|value| {
+            if value.is_nan() {
+                *value
+            } else if sd > 0.0 {
+                (value - mean) / sd
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn min_max(values: &[f64]) -> Vec<f64> {
+    let (min, max) = values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &value| {
+            (min.min(value), max.max(value))
+        });
+
+    values
+        .iter()
+        .map(|value| {
+            if value.is_nan() {
+                *value
+            } else if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn quantiles(values: &[f64]) -> Vec<f64> {
+    let mut observed: Vec<usize> =
+        (0..values.len()).filter(|&k| !values[k].is_nan()).collect();
+    observed.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut scaled = values.to_vec();
+    let last = observed.len().saturating_sub(1) as f64;
+    let mut start = 0;
+    while start < observed.len() {
+        let value = values[observed[start]];
+        let end = start
+            + observed[start..]
+                .iter()
+                .take_while(|&&k| values[k] == value)
+                .count();
+        // zero-based average rank of the tied run
+        let rank = (start + end - 1) as f64 / 2.0;
+        for &k in &observed[start..end] {
+            scaled[k] = if last > 0.0 { rank / last } else { 0.5 };
+        }
+        start = end;
+    }
+    scaled
+}
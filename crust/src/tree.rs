@@ -1,27 +1,32 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{engine::Merge, utils::CondensedMatrix};
+
+/// Direction of the leaf axis when drawing a dendrogram: `Vertical` puts
+/// leaves along x with heights growing along y (column dendrogram above a
+/// heatmap), `Horizontal` puts leaves along y with heights along x.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub enum DendrogramOrientation {
+    Horizontal,
+    Vertical,
+}
+
 #[derive(Debug)]
 pub struct Node {
     pub id: usize,
     pub parent: Option<usize>,
     pub children: Vec<usize>,
-    pub indices: Vec<usize>,
+    /// Number of leaves in the subtree, 1 for leaves.
+    pub size: usize,
+    /// Merge distance for internal nodes, 0 for leaves.
+    pub height: f64,
 }
 
 impl Node {
     pub fn update_parent(&mut self, index: usize) {
         self.parent = Some(index);
     }
-
-    pub fn get_subtree_size(&self, tree: &HcTree) -> usize {
-        if self.children.is_empty() {
-            1
-        } else {
-            self.children
-                .iter()
-                .filter_map(|child_id| tree.nodes.get(*child_id))
-                .map(|child| child.get_subtree_size(tree))
-                .sum()
-        }
-    }
 }
 
 pub struct HcTree {
@@ -42,6 +47,31 @@ pub struct LadderizedPreorderIter<'a> {
     stack: Vec<usize>,
 }
 
+/// The leaves of every subtree as a slice of one array holding all leaves
+/// in preorder, so that O(n) memory covers every node.
+pub struct LeafSpans {
+    leaves: Vec<usize>,
+    // position of every leaf in `leaves`, by node id
+    positions: Vec<usize>,
+    // (start, length) of every node's leaves in `leaves`
+    spans: Vec<(usize, usize)>,
+}
+
+impl LeafSpans {
+    /// Leaves below `node_id`, in the preorder of the tree they were taken
+    /// from.
+    pub fn leaves(&self, node_id: usize) -> &[usize] {
+        let (start, length) = self.spans[node_id];
+        &self.leaves[start..start + length]
+    }
+
+    /// Whether `leaf` lies below `node_id`.
+    pub fn contains(&self, node_id: usize, leaf: usize) -> bool {
+        let (start, length) = self.spans[node_id];
+        (start..start + length).contains(&self.positions[leaf])
+    }
+}
+
 impl<'a> Iterator for PreorderIter<'a> {
     type Item = &'a Node;
 
@@ -62,12 +92,7 @@ impl<'a> Iterator for PreOrderLeafIter<'a> {
     type Item = &'a Node;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(node) = self.inner.next() {
-            if node.children.is_empty() {
-                return Some(node);
-            }
-        }
-        None
+        self.inner.by_ref().find(|node| node.children.is_empty())
     }
 }
 
@@ -81,14 +106,14 @@ impl<'a> Iterator for LadderizedPreorderIter<'a> {
         let mut children_with_sizes: Vec<(usize, usize)> = current_node
             .children
             .iter()
-            .map(|&child_id| {
-                let size =
-                    &self.tree.nodes[child_id].get_subtree_size(self.tree);
-                (child_id, *size)
-            })
+            .map(|&child_id| (child_id, self.tree.nodes[child_id].size))
             .collect();
 
-        children_with_sizes.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        // the lower node id first among equally large children, as in
+        // `HcTree::ladderize`
+        children_with_sizes.sort_by_key(|&(child_id, size)| {
+            (std::cmp::Reverse(size), child_id)
+        });
 
         for &(child_index, _) in children_with_sizes.iter().rev() {
             self.stack.push(child_index);
@@ -105,72 +130,399 @@ impl HcTree {
                 id: index,
                 parent: None,
                 children: Vec::<usize>::with_capacity(2), // nodes should have two children
-                indices: vec![index],
+                size: 1,
+                height: 0.0,
             })
             .collect();
 
-        nodes.reserve(number_of_nodes - 1);
+        nodes.reserve(number_of_nodes.saturating_sub(1));
 
         HcTree { nodes }
     }
 
+    /// Builds a tree by replaying `merges` in order on top of
+    /// `number_of_leaves` singleton nodes.
+    pub fn from_merges(number_of_leaves: usize, merges: &[Merge]) -> HcTree {
+        let mut tree = HcTree::initialize_new_tree(number_of_leaves);
+
+        for merge in merges {
+            tree.merge(merge.first, merge.second, merge.distance);
+        }
+
+        tree
+    }
+
+    /// Rebuilds a tree from a flattened linkage matrix as produced by
+    /// `linkage_matrix`.
+    pub fn from_linkage_matrix(
+        number_of_leaves: usize,
+        linkage: &[f64],
+    ) -> HcTree {
+        let merges: Vec<Merge> = linkage
+            .chunks_exact(4)
+            .map(|row| Merge {
+                first: row[0] as usize,
+                second: row[1] as usize,
+                distance: row[2],
+            })
+            .collect();
+
+        HcTree::from_merges(number_of_leaves, &merges)
+    }
+
+    /// Joins two parentless nodes under a new node at `height` and returns
+    /// its id.
+    pub fn merge(&mut self, first: usize, second: usize, height: f64) -> usize {
+        let size = self.nodes[first].size + self.nodes[second].size;
+
+        let next_index = self.nodes.len();
+        self.nodes[first].update_parent(next_index);
+        self.nodes[second].update_parent(next_index);
+
+        self.nodes.push(Node {
+            id: next_index,
+            parent: None,
+            children: vec![first, second],
+            size,
+            height,
+        });
+
+        next_index
+    }
+
+    /// SciPy-compatible linkage matrix, flattened row by row: one
+    /// `[left, right, height, size]` row per merge in merge order, with
+    /// `left < right`.
+    pub fn linkage_matrix(&self) -> Vec<f64> {
+        self.nodes
+            .iter()
+            .filter(|node| !node.children.is_empty())
+            .flat_map(|node| {
+                let left = node.children.iter().min().copied().unwrap_or(0);
+                let right = node.children.iter().max().copied().unwrap_or(0);
+                [left as f64, right as f64, node.height, node.size as f64]
+            })
+            .collect()
+    }
+
+    /// Newick representation following the current child order. Branch
+    /// lengths are height differences between a node and its parent. Leaf
+    /// `i` is named `labels[i]`, or `i` when no labels are given.
+    pub fn to_newick(&self, labels: &[String]) -> String {
+        enum Step {
+            Enter(usize),
+            Separator,
+            Exit(usize),
+        }
+
+        assert!(
+            labels.is_empty() || labels.len() == self.number_of_leaves(),
+            "labels length must match the number of leaves"
+        );
+
+        let mut newick = String::new();
+        let mut stack: Vec<Step> = self
+            .root()
+            .map(|root| Step::Enter(root.id))
+            .into_iter()
+            .collect();
+
+        // explicit stack, chained single linkage trees can be very deep
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Enter(node_id) => {
+                    let node = &self.nodes[node_id];
+                    if node.children.is_empty() {
+                        match labels.get(node_id) {
+                            Some(label) => {
+                                newick.push_str(&newick_label(label))
+                            }
+                            None => newick.push_str(&node_id.to_string()),
+                        }
+                        self.push_branch_length(&mut newick, node);
+                        continue;
+                    }
+
+                    newick.push('(');
+                    stack.push(Step::Exit(node_id));
+                    for (position, &child_id) in
+                        node.children.iter().enumerate().rev()
+                    {
+                        stack.push(Step::Enter(child_id));
+                        if position > 0 {
+                            stack.push(Step::Separator);
+                        }
+                    }
+                }
+                Step::Separator => newick.push(','),
+                Step::Exit(node_id) => {
+                    newick.push(')');
+                    self.push_branch_length(&mut newick, &self.nodes[node_id]);
+                }
+            }
+        }
+
+        newick.push(';');
+        newick
+    }
+
+    fn push_branch_length(&self, newick: &mut String, node: &Node) {
+        if let Some(parent_id) = node.parent {
+            let length = self.nodes[parent_id].height - node.height;
+            newick.push_str(&format!(":{length}"));
+        }
+    }
+
+    /// Coordinates of the U-shaped link drawn for every merge, in merge
+    /// order and flattened as `[x1, y1, x2, y2, x3, y3, x4, y4]`: from the
+    /// first child up to the merge height, across, and down to the second
+    /// child. Leaf `k` of the current preorder sits at position `k + 0.5`,
+    /// so positions line up with the centres of unit heatmap cells, and
+    /// heights are merge heights.
+    pub fn dendrogram_segments(
+        &self,
+        orientation: DendrogramOrientation,
+    ) -> Vec<f64> {
+        let mut positions: Vec<f64> = vec![0.0; self.nodes.len()];
+        for (position, leaf) in self.preorder_leaf_traversal().enumerate() {
+            positions[leaf.id] = position as f64 + 0.5;
+        }
+
+        let mut segments: Vec<f64> = Vec::new();
+
+        // children always have smaller ids than their parent
+        for node in self.nodes.iter().filter(|node| !node.children.is_empty()) {
+            let first = &self.nodes[node.children[0]];
+            let second = &self.nodes[node.children[node.children.len() - 1]];
+            positions[node.id] =
+                (positions[first.id] + positions[second.id]) / 2.0;
+
+            let points = [
+                (positions[first.id], first.height),
+                (positions[first.id], node.height),
+                (positions[second.id], node.height),
+                (positions[second.id], second.height),
+            ];
+
+            for (position, height) in points {
+                match orientation {
+                    DendrogramOrientation::Vertical => {
+                        segments.extend([position, height])
+                    }
+                    DendrogramOrientation::Horizontal => {
+                        segments.extend([height, position])
+                    }
+                }
+            }
+        }
+
+        segments
+    }
+
+    /// Flat cluster label of every leaf after undoing the last `k - 1`
+    /// merges, like R's `cutree(tree, k = k)`. Labels start at 1 and are
+    /// numbered in the order their first leaf appears in the input.
+    pub fn cut_by_count(&self, k: usize) -> Vec<usize> {
+        let number_of_leaves = self.number_of_leaves();
+        let kept_merges = number_of_leaves.saturating_sub(k.max(1));
+
+        self.flat_clusters(|node| node.id < number_of_leaves + kept_merges)
+    }
+
+    /// Flat cluster label of every leaf, joining leaves whose subtree is no
+    /// higher than `height`, like SciPy's `fcluster` with the `distance`
+    /// criterion. Labels are numbered as in `cut_by_count`.
+    pub fn cut_by_height(&self, height: f64) -> Vec<usize> {
+        // highest merge below every node, for linkages that are not monotone
+        let mut subtree_heights: Vec<f64> = vec![0.0; self.nodes.len()];
+        for node in &self.nodes {
+            subtree_heights[node.id] = node
+                .children
+                .iter()
+                .map(|&child_id| subtree_heights[child_id])
+                .fold(node.height, f64::max);
+        }
+
+        self.flat_clusters(|node| subtree_heights[node.id] <= height)
+    }
+
+    /// Labels leaves by their highest ancestor for which `is_joined` holds.
+    /// `is_joined` must hold for every child of a node it holds for.
+    fn flat_clusters(&self, is_joined: impl Fn(&Node) -> bool) -> Vec<usize> {
+        let mut cluster_roots: Vec<usize> = vec![0; self.nodes.len()];
+
+        // parents always have larger ids than their children
+        for node in self.nodes.iter().rev() {
+            cluster_roots[node.id] = match node.parent {
+                Some(parent_id) if is_joined(&self.nodes[parent_id]) => {
+                    cluster_roots[parent_id]
+                }
+                _ => node.id,
+            };
+        }
+
+        let mut labels: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut next_label = 1;
+
+        (0..self.number_of_leaves())
+            .map(|leaf| {
+                *labels[cluster_roots[leaf]].get_or_insert_with(|| {
+                    next_label += 1;
+                    next_label - 1
+                })
+            })
+            .collect()
+    }
+
+    fn number_of_leaves(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.children.is_empty())
+            .count()
+    }
+
+    /// Cophenetic distances between all leaves: the height of the merge
+    /// that first joins each pair.
+    pub fn cophenetic_distances(&self) -> CondensedMatrix {
+        let number_of_leaves = self.number_of_leaves();
+        let mut distances = CondensedMatrix::new(
+            vec![
+                0.0;
+                number_of_leaves * number_of_leaves.saturating_sub(1) / 2
+            ],
+            number_of_leaves,
+        );
+
+        let spans = self.leaf_spans();
+
+        for node in &self.nodes {
+            for (position, &first) in node.children.iter().enumerate() {
+                for &second in &node.children[position + 1..] {
+                    for &i in spans.leaves(first) {
+                        for &j in spans.leaves(second) {
+                            distances.set(i, j, node.height);
+                        }
+                    }
+                }
+            }
+        }
+
+        distances
+    }
+
+    /// The leaves below every node, see `LeafSpans`.
+    pub fn leaf_spans(&self) -> LeafSpans {
+        let leaves: Vec<usize> =
+            self.preorder_leaf_traversal().map(|leaf| leaf.id).collect();
+        let mut positions: Vec<usize> = vec![0; self.nodes.len()];
+        for (position, &leaf) in leaves.iter().enumerate() {
+            positions[leaf] = position;
+        }
+
+        // a subtree's leaves are consecutive in preorder, starting with
+        // those of its first child; children have smaller ids than parents
+        let mut spans: Vec<(usize, usize)> = vec![(0, 0); self.nodes.len()];
+        for node in &self.nodes {
+            let start = match node.children.first() {
+                Some(&first_child) => spans[first_child].0,
+                None => positions[node.id],
+            };
+            spans[node.id] = (start, node.size);
+        }
+
+        LeafSpans {
+            leaves,
+            positions,
+            spans,
+        }
+    }
+
     pub fn root(&self) -> Option<&Node> {
         self.nodes.last()
     }
 
-    pub fn preorder_node_traversal(&self) -> PreorderIter {
+    pub fn preorder_node_traversal(&self) -> PreorderIter<'_> {
         let root_index = self.nodes.len().checked_sub(1); // assuming root is the last node
         let stack = root_index.map_or_else(Vec::new, |idx| vec![idx]);
 
         PreorderIter { tree: self, stack }
     }
 
-    pub fn preorder_leaf_traversal(&self) -> PreOrderLeafIter {
+    pub fn preorder_leaf_traversal(&self) -> PreOrderLeafIter<'_> {
         PreOrderLeafIter {
             inner: self.preorder_node_traversal(),
         }
     }
 
-    pub fn ladderized_preorder_node_view(&self) -> LadderizedPreorderIter {
+    pub fn ladderized_preorder_node_view(&self) -> LadderizedPreorderIter<'_> {
         let root_index = self.nodes.len().checked_sub(1);
         let stack = root_index.map_or_else(Vec::new, |idx| vec![idx]);
 
-        LadderizedPreorderIter {
-            tree: self,
-            stack,
-        }
+        LadderizedPreorderIter { tree: self, stack }
     }
 
+    /// Puts larger subtrees first at every node. Equally large subtrees are
+    /// ordered by node id, the lower one first, whatever their current
+    /// order: for the tree built from the merges that is the left child
+    /// SciPy would draw.
     pub fn ladderize(&mut self) {
-        if let Some(root_id) = self.nodes.last().map(|n| n.id) {
-            self.ladderize_node(root_id);
-        }
+        self.sort_children(false);
     }
 
-    fn ladderize_node(&mut self, node_id: usize) {
-        let subtree_sizes = self.nodes[node_id]
-            .children
-            .iter()
-            .map(|&child_id| {
-                let size = self.nodes[child_id].get_subtree_size(&self);
-                (child_id, size)
-            })
-            .collect::<Vec<_>>();
-
-        // Sort children in descending order of subtree size
-        let sorted_children: Vec<usize> = {
-            let mut pairs = subtree_sizes;
-            pairs.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
-            pairs.into_iter().map(|(id, _)| id).collect()
-        };
+    /// Puts smaller subtrees first at every node, ties as in `ladderize`.
+    pub fn ladderize_ascending(&mut self) {
+        self.sort_children(true);
+    }
 
-        self.nodes[node_id].children = sorted_children;
+    /// Sorts the children of every node by subtree size, descending unless
+    /// `ascending`, and by node id on ties. Node by node rather than down
+    /// from the root, chained single linkage trees can be very deep.
+    fn sort_children(&mut self, ascending: bool) {
+        let sizes: Vec<usize> =
+            self.nodes.iter().map(|node| node.size).collect();
+
+        for node in self.nodes.iter_mut() {
+            if ascending {
+                node.children.sort_by_key(|&id| (sizes[id], id));
+            } else {
+                node.children
+                    .sort_by_key(|&id| (std::cmp::Reverse(sizes[id]), id));
+            }
+        }
+    }
 
-        // ✅ FIX: clone child list *before* recursive calls
-        let child_ids = self.nodes[node_id].children.clone();
+    /// Orders the children of every node by increasing sum of the `weights`
+    /// of their leaves, keeping the current order on ties, like R's
+    /// `reorder.dendrogram` with its default `agglo.FUN = sum`.
+    pub fn reorder_by_weights(&mut self, weights: &[f64]) {
+        // bottom up, children have smaller ids than their parent
+        let mut node_weights: Vec<f64> = vec![0.0; self.nodes.len()];
+        for node in &self.nodes {
+            node_weights[node.id] = if node.children.is_empty() {
+                weights[node.id]
+            } else {
+                node.children.iter().map(|&child| node_weights[child]).sum()
+            };
+        }
 
-        for child_id in child_ids {
-            self.ladderize_node(child_id);
+        for node in self.nodes.iter_mut() {
+            node.children.sort_by(|first, second| {
+                node_weights[*first].total_cmp(&node_weights[*second])
+            });
         }
     }
 }
+
+/// Quotes labels containing characters with a meaning in Newick.
+fn newick_label(label: &str) -> String {
+    let needs_quotes = label.chars().any(|c| {
+        c.is_whitespace()
+            || matches!(c, '(' | ')' | '[' | ']' | '\'' | ':' | ';' | ',')
+    });
+
+    if needs_quotes {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    }
+}
@@ -1,13 +1,42 @@
 use ::std::ops::Index;
-use core::fmt;
+use std::borrow::Cow;
 
+/// Where element (i, j) of a `MatrixView` sits in its data.
+#[derive(Debug, Clone, Copy)]
+enum Layout<'a> {
+    /// At `i * row_stride + j * col_stride`: row-major data has a column
+    /// stride of 1, transposing swaps the strides.
+    Strided {
+        row_stride: usize,
+        col_stride: usize,
+    },
+    /// Strict upper triangle of a square matrix stored row by row, zero on
+    /// and below the diagonal. `transposed` mirrors it.
+    UpperTriangular { transposed: bool },
+    /// Strict lower triangle stored row by row, see `UpperTriangular`.
+    LowerTriangular { transposed: bool },
+    /// The `rows` and `cols` of a strided matrix in that order, all of
+    /// them in their own order where `None`.
+    Permuted {
+        row_stride: usize,
+        col_stride: usize,
+        rows: Option<&'a [usize]>,
+        cols: Option<&'a [usize]>,
+    },
+}
+
+/// Borrowed two-dimensional view of a flat slice. Views are cheap to copy,
+/// and transposing or permuting one only changes how it is indexed.
+#[derive(Debug, Clone, Copy)]
 pub struct MatrixView<'a> {
     data: &'a [f64],
     nrows: usize,
     ncols: usize,
-    get_index: Box<dyn Fn(usize, usize) -> usize + 'a>,
+    layout: Layout<'a>,
 }
 
+static MASKED: f64 = 0.0;
+
 impl<'a> MatrixView<'a> {
     pub fn new(data: &'a [f64], nrows: usize, ncols: usize) -> Self {
         assert_eq!(
@@ -19,7 +48,10 @@ impl<'a> MatrixView<'a> {
             data,
             nrows,
             ncols,
-            get_index: Box::new(move |i, j| i * ncols + j),
+            layout: Layout::Strided {
+                row_stride: ncols,
+                col_stride: 1,
+            },
         }
     }
 
@@ -29,7 +61,7 @@ impl<'a> MatrixView<'a> {
         ncols: usize,
     ) -> Self {
         assert_eq!(
-            nrows * (nrows - 1) / 2,
+            nrows * nrows.saturating_sub(1) / 2,
             data.len(),
             "data length must match nrows*(nrows-1)/2"
         );
@@ -38,13 +70,7 @@ impl<'a> MatrixView<'a> {
             data,
             nrows,
             ncols,
-            get_index: Box::new(move |i, j| {
-                if i >= j {
-                    usize::MAX // sentinel for masked-out elements
-                } else {
-                    i * ncols - (i * (i + 1)) / 2 + (j - i - 1)
-                }
-            }),
+            layout: Layout::UpperTriangular { transposed: false },
         }
     }
 
@@ -54,7 +80,7 @@ impl<'a> MatrixView<'a> {
         ncols: usize,
     ) -> Self {
         assert_eq!(
-            nrows * (nrows - 1) / 2,
+            nrows * nrows.saturating_sub(1) / 2,
             data.len(),
             "data length must match nrows*(nrows-1)/2"
         );
@@ -63,86 +89,162 @@ impl<'a> MatrixView<'a> {
             data,
             nrows,
             ncols,
-            get_index: Box::new(move |i, j| {
-                if i <= j {
-                    usize::MAX // sentinel for masked-out elements
-                } else {
-                    i * (i - 1) / 2 + j
-                }
-            }),
+            layout: Layout::LowerTriangular { transposed: false },
         }
     }
 
-    pub fn transposed(&'a self) -> MatrixView<'a> {
-        let previous_get_index_fn = &self.get_index;
+    pub fn transposed(&self) -> MatrixView<'a> {
+        let layout = match self.layout {
+            Layout::Strided {
+                row_stride,
+                col_stride,
+            } => Layout::Strided {
+                row_stride: col_stride,
+                col_stride: row_stride,
+            },
+            Layout::UpperTriangular { transposed } => Layout::UpperTriangular {
+                transposed: !transposed,
+            },
+            Layout::LowerTriangular { transposed } => Layout::LowerTriangular {
+                transposed: !transposed,
+            },
+            Layout::Permuted {
+                row_stride,
+                col_stride,
+                rows,
+                cols,
+            } => Layout::Permuted {
+                row_stride: col_stride,
+                col_stride: row_stride,
+                rows: cols,
+                cols: rows,
+            },
+        };
+
         MatrixView {
             data: self.data,
             nrows: self.ncols,
             ncols: self.nrows,
-            get_index: Box::new(move |i, j| (*previous_get_index_fn)(j, i)),
+            layout,
         }
     }
 
-    pub fn get(&self, i: usize, j: usize) -> f64 {
-        let index = (self.get_index)(i, j);
-        if index == usize::MAX {
-            0.0
-        } else {
-            self.data[index]
+    /// Position of element (i, j) in the data, `None` for the masked-out
+    /// half of a triangular view.
+    #[inline]
+    fn index(&self, i: usize, j: usize) -> Option<usize> {
+        match self.layout {
+            Layout::Strided {
+                row_stride,
+                col_stride,
+            } => Some(i * row_stride + j * col_stride),
+            Layout::UpperTriangular { transposed } => {
+                let (i, j) = if transposed { (j, i) } else { (i, j) };
+                (i < j)
+                    .then(|| i * self.nrows - (i * (i + 1)) / 2 + (j - i - 1))
+            }
+            Layout::LowerTriangular { transposed } => {
+                let (i, j) = if transposed { (j, i) } else { (i, j) };
+                (i > j).then(|| i * (i - 1) / 2 + j)
+            }
+            Layout::Permuted {
+                row_stride,
+                col_stride,
+                rows,
+                cols,
+            } => {
+                let i = rows.map_or(i, |rows| rows[i]);
+                let j = cols.map_or(j, |cols| cols[j]);
+                Some(i * row_stride + j * col_stride)
+            }
         }
     }
 
-    pub fn row_permutation(&'a self, row_order: &'a [usize]) -> MatrixView<'a> {
-        MatrixView {
-            data: self.data,
-            nrows: self.nrows,
-            ncols: self.ncols,
-            get_index: Box::new(move |i, j| row_order[i] * self.ncols + j),
-        }
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        self.index(i, j).map_or(0.0, |index| self.data[index])
     }
 
-    pub fn col_permutation(&'a self, col_order: &'a [usize]) -> MatrixView<'a> {
-        MatrixView {
-            data: self.data,
-            nrows: self.nrows,
-            ncols: self.ncols,
-            get_index: Box::new(move |i, j| i * self.ncols + col_order[j]),
+    /// Row `i` as a slice of the data itself, when its elements are stored
+    /// next to each other.
+    pub fn row_slice(&self, i: usize) -> Option<&'a [f64]> {
+        match self.layout {
+            Layout::Strided {
+                row_stride,
+                col_stride: 1,
+            } => Some(&self.data[i * row_stride..i * row_stride + self.ncols]),
+            _ => None,
         }
     }
 
+    /// All elements row by row in a new vector.
+    pub fn to_row_major(self) -> Vec<f64> {
+        (0..self.nrows)
+            .flat_map(|i| (0..self.ncols).map(move |j| self.get(i, j)))
+            .collect()
+    }
+
+    /// The rows in `row_order`, read through this view without a copy.
+    ///
+    /// Panics on a triangular view, copy it with `to_row_major` first.
+    pub fn row_permutation(&self, row_order: &'a [usize]) -> MatrixView<'a> {
+        self.permuted(Some(row_order), None)
+    }
+
+    /// The columns in `col_order`, read through this view without a copy.
+    ///
+    /// Panics on a triangular view, copy it with `to_row_major` first.
+    pub fn col_permutation(&self, col_order: &'a [usize]) -> MatrixView<'a> {
+        self.permuted(None, Some(col_order))
+    }
+
+    /// The rows in `row_order` and the columns in `col_order`, read through
+    /// this view without a copy.
+    ///
+    /// Panics on a triangular view, copy it with `to_row_major` first.
     pub fn permutation(
-        &'a self,
+        &self,
         row_order: &'a [usize],
         col_order: &'a [usize],
     ) -> MatrixView<'a> {
+        self.permuted(Some(row_order), Some(col_order))
+    }
+
+    /// Only views of a strided matrix can be permuted.
+    fn permuted(
+        &self,
+        rows: Option<&'a [usize]>,
+        cols: Option<&'a [usize]>,
+    ) -> MatrixView<'a> {
+        let Layout::Strided {
+            row_stride,
+            col_stride,
+        } = self.layout
+        else {
+            panic!("only strided views can be permuted");
+        };
+
         MatrixView {
             data: self.data,
-            nrows: self.nrows,
-            ncols: self.ncols,
-            get_index: Box::new(move |i, j| {
-                row_order[i] * self.ncols + col_order[j]
-            }),
+            nrows: rows.map_or(self.nrows, <[usize]>::len),
+            ncols: cols.map_or(self.ncols, <[usize]>::len),
+            layout: Layout::Permuted {
+                row_stride,
+                col_stride,
+                rows,
+                cols,
+            },
         }
     }
 }
 
-impl<'a> fmt::Debug for MatrixView<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("MatrixView")
-            .field("data", &self.data)
-            .field("nrows", &self.nrows)
-            .field("ncols", &self.ncols)
-            .field("get_index", &"<function>")
-            .finish()
-    }
-}
-
 impl<'a> Index<(usize, usize)> for MatrixView<'a> {
     type Output = f64;
 
     fn index(&self, index: (usize, usize)) -> &Self::Output {
-        let idx = (self.get_index)(index.0, index.1);
-        &self.data[idx]
+        match self.index(index.0, index.1) {
+            Some(index) => &self.data[index],
+            None => &MASKED,
+        }
     }
 }
 
@@ -150,8 +252,10 @@ pub trait MatrixLike {
     fn nrows(&self) -> usize;
     fn ncols(&self) -> usize;
     fn get(&self, i: usize, j: usize) -> f64;
-    fn row(&self, i: usize) -> Vec<f64> {
-        (0..self.ncols()).map(|j| self.get(i, j)).collect()
+    /// Row `i`, borrowed where the matrix stores it contiguously and
+    /// gathered into a new vector otherwise.
+    fn row(&self, i: usize) -> Cow<'_, [f64]> {
+        Cow::Owned((0..self.ncols()).map(|j| self.get(i, j)).collect())
     }
     fn col(&self, j: usize) -> Vec<f64> {
         (0..self.nrows()).map(|i| self.get(i, j)).collect()
@@ -168,4 +272,72 @@ impl<'a> MatrixLike for MatrixView<'a> {
     fn get(&self, i: usize, j: usize) -> f64 {
         self.get(i, j)
     }
+    fn row(&self, i: usize) -> Cow<'_, [f64]> {
+        match self.row_slice(i) {
+            Some(row) => Cow::Borrowed(row),
+            None => {
+                Cow::Owned((0..self.ncols).map(|j| self.get(i, j)).collect())
+            }
+        }
+    }
+}
+
+/// Owned, mutable upper triangle of a symmetric `size` x `size` matrix,
+/// stored row by row without the diagonal.
+#[derive(Debug, Clone)]
+pub struct CondensedMatrix {
+    data: Vec<f64>,
+    size: usize,
+}
+
+impl CondensedMatrix {
+    pub fn new(data: Vec<f64>, size: usize) -> Self {
+        assert_eq!(
+            size * size.saturating_sub(1) / 2,
+            data.len(),
+            "data length must match size*(size-1)/2"
+        );
+        CondensedMatrix { data, size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn index(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i < j { (i, j) } else { (j, i) };
+        i * self.size - (i * (i + 1)) / 2 + (j - i - 1)
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        self.data[self.index(i, j)]
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, value: f64) {
+        let index = self.index(i, j);
+        self.data[index] = value;
+    }
+
+    /// Upper triangle row by row, as in `compute_distance_matrix_from_view`.
+    pub fn as_slice(&self) -> &[f64] {
+        &self.data
+    }
+
+    /// Distances between the observations at `indices` only, in that order.
+    pub fn select(&self, indices: &[usize]) -> CondensedMatrix {
+        let mut data: Vec<f64> = Vec::with_capacity(
+            indices.len() * indices.len().saturating_sub(1) / 2,
+        );
+        for (position, &i) in indices.iter().enumerate() {
+            data.extend(
+                indices[position + 1..].iter().map(|&j| self.get(i, j)),
+            );
+        }
+
+        CondensedMatrix::new(data, indices.len())
+    }
+
+    pub fn map_in_place(&mut self, f: impl Fn(f64) -> f64) {
+        self.data.iter_mut().for_each(|value| *value = f(*value));
+    }
 }
@@ -1,10 +1,110 @@
 // worker.ts
 import init, {
   hierarchical_clustering,
+  distance_matrix_clustering,
+  DistanceMatrixLayout,
   ClusteringAxis,
   LinkageFunction,
   DistanceMetric,
+  DendrogramOrientation,
+  LeafOrdering,
+  ClusteringOptions,
+  DynamicCutMethod,
+  DynamicCutOptions,
+  NanPolicy,
+  Normalization,
+  Scaling,
 } from "../../wasm/crust"; // Adjust path as needed
+import * as crust from "../../wasm/crust";
+
+// only builds with the `parallel` feature export them, one of the two sets
+// up rayon's threads before any clustering
+const { initThreadPool, initSingleThread } = crust as unknown as {
+  initThreadPool?: (threads: number) => Promise<void>;
+  initSingleThread?: () => void;
+};
+
+let initialized: Promise<void> | undefined;
+
+// loads the module once, with a thread per core where available; the
+// threads share memory, which needs a cross-origin isolated page, and
+// clustering stays on this worker's thread otherwise
+function initialize(): Promise<void> {
+  initialized ??= init().then(async () => {
+    if (initThreadPool && self.crossOriginIsolated) {
+      await initThreadPool(navigator.hardwareConcurrency);
+    } else {
+      initSingleThread?.();
+    }
+  });
+  return initialized;
+}
+
+// settings shared by both entry points, `free()` the returned options
+function clusteringOptions({
+  minkowskiP,
+  ordering,
+  rowWeights,
+  colWeights,
+  dynamicCut,
+  copheneticCorrelation,
+  nanPolicy,
+  normalization,
+  logTransform,
+  scaling,
+}: {
+  minkowskiP?: number;
+  ordering?: string;
+  rowWeights?: Float64Array;
+  colWeights?: Float64Array;
+  dynamicCut?: {
+    method: string;
+    minModuleSize?: number;
+    deepSplit?: number;
+    cutHeight?: number;
+    pamStage?: boolean;
+  };
+  copheneticCorrelation?: boolean;
+  nanPolicy?: string;
+  normalization?: string;
+  logTransform?: boolean;
+  scaling?: string;
+}): ClusteringOptions {
+  const options = new ClusteringOptions();
+  options.minkowski_p = minkowskiP;
+  options.ordering =
+    LeafOrdering[
+      (ordering ?? "LadderizeDescending") as keyof typeof LeafOrdering
+    ];
+  options.row_weights = rowWeights;
+  options.col_weights = colWeights;
+  options.cophenetic_correlation = copheneticCorrelation ?? false;
+  options.nan_policy =
+    NanPolicy[(nanPolicy ?? "PairwiseComplete") as keyof typeof NanPolicy];
+  options.normalization =
+    Normalization[(normalization ?? "None") as keyof typeof Normalization];
+  options.log_transform = logTransform ?? false;
+  options.scaling = Scaling[(scaling ?? "None") as keyof typeof Scaling];
+  if (dynamicCut) {
+    const cut = new DynamicCutOptions(
+      DynamicCutMethod[dynamicCut.method as keyof typeof DynamicCutMethod]
+    );
+    if (dynamicCut.minModuleSize !== undefined) {
+      cut.min_module_size = dynamicCut.minModuleSize;
+    }
+    if (dynamicCut.deepSplit !== undefined) {
+      cut.deep_split = dynamicCut.deepSplit;
+    }
+    if (dynamicCut.cutHeight !== undefined) {
+      cut.cut_height = dynamicCut.cutHeight;
+    }
+    if (dynamicCut.pamStage !== undefined) {
+      cut.pam_stage = dynamicCut.pamStage;
+    }
+    options.dynamic_cut = cut;
+  }
+  return options;
+}
 
 // onmessage = async (event: MessageEvent) => {
 //   const {type, payload } = event.data;
@@ -16,9 +116,20 @@ onmessage = async (event: MessageEvent) => {
   if (type === "hierarchical_clustering") {
     try {
       // Ensure WASM is initialized
-      await init();
+      await initialize();
 
-      const { nrows, ncols, values, axis, linkage, distance } = payload;
+      const {
+        nrows,
+        ncols,
+        values,
+        axis,
+        linkage,
+        distance,
+        clusterCount,
+        dynamicCut,
+        rowLabels,
+        colLabels,
+      } = payload;
 
       console.log(axis, linkage, distance);
 
@@ -27,19 +138,44 @@ onmessage = async (event: MessageEvent) => {
         LinkageFunction[linkage as keyof typeof LinkageFunction];
       const distanceEnum =
         DistanceMetric[distance as keyof typeof DistanceMetric];
+
+      const options = clusteringOptions(payload);
+
       const result = hierarchical_clustering(
         nrows,
         ncols,
         values,
         axisEnum,
         linkageEnum,
-        distanceEnum
+        distanceEnum,
+        options
       );
+      options.free();
 
       // result is an instance of HierarchicalClusteringResult
       const row_order = result.row_order;
       const col_order = result.col_order;
       const clustered_values = result.values;
+      const row_linkage = result.row_linkage;
+      const col_linkage = result.col_linkage;
+      const row_newick = rowLabels ? result.row_newick(rowLabels) : undefined;
+      const col_newick = colLabels ? result.col_newick(colLabels) : undefined;
+      // row dendrogram sits left of the heatmap, column dendrogram above it
+      const row_dendrogram = result.row_dendrogram(
+        DendrogramOrientation.Horizontal
+      );
+      const col_dendrogram = result.col_dendrogram(
+        DendrogramOrientation.Vertical
+      );
+      // flat cluster label per row/column in input order
+      const row_clusters =
+        clusterCount !== undefined
+          ? result.cut_rows_by_count(clusterCount)
+          : undefined;
+      const col_clusters =
+        clusterCount !== undefined
+          ? result.cut_cols_by_count(clusterCount)
+          : undefined;
 
       self.postMessage({
         type: "result",
@@ -47,17 +183,88 @@ onmessage = async (event: MessageEvent) => {
           row_order,
           col_order,
           values: clustered_values,
+          row_linkage,
+          col_linkage,
+          row_newick,
+          col_newick,
+          row_dendrogram,
+          col_dendrogram,
+          row_clusters,
+          col_clusters,
+          row_modules: dynamicCut ? result.row_modules : undefined,
+          col_modules: dynamicCut ? result.col_modules : undefined,
+          row_cophenetic_correlation: result.row_cophenetic_correlation,
+          col_cophenetic_correlation: result.col_cophenetic_correlation,
+          unplaced_rows: result.unplaced_rows,
+          unplaced_cols: result.unplaced_cols,
+        },
+      });
+    } catch (err) {
+      postError(err);
+    }
+  } else if (type === "distance_matrix_clustering") {
+    try {
+      await initialize();
+
+      const {
+        size,
+        distances,
+        layout,
+        linkage,
+        clusterCount,
+        dynamicCut,
+        labels,
+      } = payload;
+
+      const options = clusteringOptions(payload);
+      const result = distance_matrix_clustering(
+        size,
+        distances,
+        DistanceMatrixLayout[layout as keyof typeof DistanceMatrixLayout],
+        LinkageFunction[linkage as keyof typeof LinkageFunction],
+        options
+      );
+      options.free();
+
+      // the columns follow the rows and carry no tree of their own
+      self.postMessage({
+        type: "result",
+        payload: {
+          row_order: result.row_order,
+          col_order: result.col_order,
+          values: result.values,
+          row_linkage: result.row_linkage,
+          col_linkage: result.col_linkage,
+          row_newick: labels ? result.row_newick(labels) : undefined,
+          row_dendrogram: result.row_dendrogram(
+            DendrogramOrientation.Horizontal
+          ),
+          col_dendrogram: result.col_dendrogram(
+            DendrogramOrientation.Vertical
+          ),
+          row_clusters:
+            clusterCount !== undefined
+              ? result.cut_rows_by_count(clusterCount)
+              : undefined,
+          row_modules: dynamicCut ? result.row_modules : undefined,
+          row_cophenetic_correlation: result.row_cophenetic_correlation,
+          unplaced_rows: result.unplaced_rows,
+          unplaced_cols: result.unplaced_cols,
         },
       });
     } catch (err) {
-      if (err instanceof Error) {
-        self.postMessage({ type: "error", payload: err.message });
-      } else {
-        self.postMessage({
-          type: "error",
-          payload: String(err),
-        });
-      }
+      postError(err);
     }
   }
 };
+
+function postError(err: unknown) {
+  if (err instanceof Error) {
+    self.postMessage({ type: "error", payload: err.message });
+  } else {
+    self.postMessage({
+      type: "error",
+      payload: String(err),
+    });
+  }
+}
@@ -1,19 +1,43 @@
-import { useMemo } from "react";
-import { DataScalers, DataScalingOptions } from "../scaling";
-import { hierarchicalClustering } from "../clustering/cluster";
-import { getVectors } from "../clustering/utils";
+import { CrustScalings, DataScalingOptions } from "../scaling";
 import { DistanceMetricOptions, LinkageMetricOptions } from "../clustering";
+import {
+  useCrust,
+  ClusteringAxis,
+  DistanceMetric,
+  LinkageFunction,
+} from "./useCrust";
 
 interface ClusteringHookProps {
   data: number[];
   nrows: number;
   ncols: number;
   scalingFunctionName: DataScalingOptions;
-  clusterAxis: string;
+  clusterAxis: "row" | "col" | "both";
   distanceMetric: DistanceMetricOptions;
   clusterLinkage: LinkageMetricOptions;
 }
 
+const ClusteringAxes: Record<
+  ClusteringHookProps["clusterAxis"],
+  ClusteringAxis
+> = {
+  row: "Row",
+  col: "Column",
+  both: "Both",
+};
+
+const DistanceMetricNames: Record<DistanceMetricOptions, DistanceMetric> = {
+  euclidean: "Euclidean",
+  chebyshev: "Chebyshev",
+};
+
+const LinkageFunctionNames: Record<LinkageMetricOptions, LinkageFunction> = {
+  average: "Average",
+  ward: "Ward",
+};
+
+// the JS option names on top of crust, which scales and clusters the values
+// in its worker
 export const useClustering = ({
   data,
   nrows,
@@ -23,31 +47,15 @@ export const useClustering = ({
   distanceMetric,
   clusterLinkage,
 }: ClusteringHookProps) => {
-  const scaledData = useMemo(() => {
-    return DataScalers[scalingFunctionName].function({ data, nrows, ncols });
-  }, [scalingFunctionName, data, nrows, ncols]);
-
-  const rowOrder = useMemo(() => {
-    return clusterAxis === "row" || clusterAxis === "both"
-      ? hierarchicalClustering({
-          data: getVectors(scaledData, nrows, ncols, "row"),
-          distanceMetric: distanceMetric,
-          linkageMetric: clusterLinkage,
-          by: "row",
-        })
-      : Array.from({ length: nrows }, (_, index) => index);
-  }, [scaledData, nrows, ncols, clusterAxis, distanceMetric, clusterLinkage]);
-
-  const colOrder = useMemo(() => {
-    return clusterAxis === "col" || clusterAxis === "both"
-      ? hierarchicalClustering({
-          data: getVectors(scaledData, nrows, ncols, "col"),
-          distanceMetric: distanceMetric,
-          linkageMetric: clusterLinkage,
-          by: "col",
-        })
-      : Array.from({ length: ncols }, (_, index) => index);
-  }, [scaledData, ncols, nrows, clusterAxis, distanceMetric, clusterLinkage]);
+  const { rowOrder, colOrder, values, error } = useCrust({
+    data,
+    nrows,
+    ncols,
+    axis: ClusteringAxes[clusterAxis],
+    linkage: LinkageFunctionNames[clusterLinkage],
+    distance: DistanceMetricNames[distanceMetric],
+    ...CrustScalings[scalingFunctionName],
+  });
 
-  return { rowOrder, colOrder, values: scaledData };
+  return { rowOrder, colOrder, values, error };
 };
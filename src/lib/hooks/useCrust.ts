@@ -1,8 +1,55 @@
 import { useEffect, useRef, useState } from "react";
 
 export type ClusteringAxis = "Row" | "Column" | "Both";
-export type LinkageFunction = "Average" | "Ward";
-export type DistanceMetric = "Euclidean" | "Chebyshev";
+export type LinkageFunction =
+  | "Average"
+  | "Ward"
+  | "Single"
+  | "Complete"
+  | "Weighted"
+  | "Centroid"
+  | "Median";
+export type DistanceMetric =
+  | "Euclidean"
+  | "SquaredEuclidean"
+  | "Chebyshev"
+  | "Pearson"
+  | "Spearman"
+  | "Cosine"
+  | "Manhattan"
+  | "Canberra"
+  | "Minkowski";
+export type LeafOrdering =
+  | "LadderizeDescending"
+  | "LadderizeAscending"
+  | "MergeOrder"
+  | "MeanValue"
+  | "Weights"
+  | "Optimal";
+// handling of missing values, see `NanPolicy` in crust
+export type NanPolicy = "PairwiseComplete" | "DropIncomplete" | "ImputeRowMean";
+// sample normalisation of count columns, see `Normalization` in crust
+export type Normalization = "None" | "Quantile" | "MedianOfRatios" | "Tmm";
+// rescaling of the values before clustering, see `Scaling` in crust
+export type Scaling =
+  | "None"
+  | "RowZScore"
+  | "ColumnZScore"
+  | "RowMinMax"
+  | "ColumnMinMax"
+  | "Quantile";
+// upper triangle row by row (SciPy `pdist` order) or the full square matrix
+export type DistanceMatrixLayout = "Condensed" | "Square";
+
+// WGCNA-style co-expression module detection, see `cutreeDynamic`
+export interface DynamicCutSettings {
+  method: "Hybrid" | "Tree";
+  minModuleSize?: number;
+  // 0 (few, large modules) to 4 (many, small modules)
+  deepSplit?: number;
+  cutHeight?: number;
+  pamStage?: boolean;
+}
 
 interface HierarchicalClusteringArgs {
   nrows: number;
@@ -11,17 +58,82 @@ interface HierarchicalClusteringArgs {
   axis: ClusteringAxis;
   linkage: LinkageFunction;
   distance: DistanceMetric;
+  minkowskiP?: number;
+  ordering?: LeafOrdering;
+  // one weight per row/column, required by the "Weights" ordering
+  rowWeights?: Float64Array;
+  colWeights?: Float64Array;
+  // cut both dendrograms into this many flat clusters
+  clusterCount?: number;
+  dynamicCut?: DynamicCutSettings;
+  // report how well each dendrogram fits its distances
+  copheneticCorrelation?: boolean;
+  nanPolicy?: NanPolicy;
+  // applied to the columns before `logTransform` and `scaling`
+  normalization?: Normalization;
+  // log2(x + 1) before `scaling`; the returned values are the scaled ones
+  logTransform?: boolean;
+  scaling?: Scaling;
+  rowLabels?: string[];
+  colLabels?: string[];
 }
 
-type CrustWorkerRequest = {
-  type: "hierarchical_clustering";
-  payload: HierarchicalClusteringArgs;
-};
+interface DistanceMatrixClusteringArgs {
+  size: number;
+  distances: Float64Array;
+  layout: DistanceMatrixLayout;
+  linkage: LinkageFunction;
+  // "MeanValue" needs raw values and is rejected
+  ordering?: LeafOrdering;
+  // one weight per observation, the worker shares its options with the
+  // row axis of `hierarchical_clustering`
+  rowWeights?: Float64Array;
+  clusterCount?: number;
+  dynamicCut?: DynamicCutSettings;
+  copheneticCorrelation?: boolean;
+  // "ImputeRowMean" needs raw values and is rejected
+  nanPolicy?: NanPolicy;
+  labels?: string[];
+}
+
+type CrustWorkerRequest =
+  | {
+      type: "hierarchical_clustering";
+      payload: HierarchicalClusteringArgs;
+    }
+  | {
+      type: "distance_matrix_clustering";
+      payload: DistanceMatrixClusteringArgs;
+    };
 
 interface HierarchicalClusteringResult {
   row_order: Uint32Array;
   col_order: Uint32Array;
   values: Float64Array;
+  // SciPy linkage matrices flattened as [left, right, height, size] rows
+  row_linkage: Float64Array;
+  col_linkage: Float64Array;
+  // only present when labels were sent along with the request
+  row_newick?: string;
+  col_newick?: string;
+  // U-shaped links flattened as [x1, y1, x2, y2, x3, y3, x4, y4], leaf k
+  // centred at k + 0.5 along the leaf axis
+  row_dendrogram: Float64Array;
+  col_dendrogram: Float64Array;
+  // 1-based flat cluster labels in input order, only present when
+  // `clusterCount` was requested and the axis was clustered
+  row_clusters?: Uint32Array;
+  col_clusters?: Uint32Array;
+  // module label per row/column in input order, 0 outside of every module
+  row_modules?: Uint32Array;
+  col_modules?: Uint32Array;
+  // only present when `copheneticCorrelation` was requested
+  row_cophenetic_correlation?: number;
+  col_cophenetic_correlation?: number;
+  // rows/columns missing from the dendrograms because of NaN values, in
+  // input order; they come last in `row_order`/`col_order`
+  unplaced_rows: Uint32Array;
+  unplaced_cols: Uint32Array;
 }
 
 type CrustWorkerSuccessResponse = {
@@ -45,6 +157,24 @@ interface CrustHookProps {
   axis: ClusteringAxis;
   linkage: LinkageFunction;
   distance: DistanceMetric;
+  minkowskiP?: number;
+  ordering?: LeafOrdering;
+  // one weight per row/column, required by the "Weights" ordering
+  rowWeights?: Float64Array;
+  colWeights?: Float64Array;
+  // cut both dendrograms into this many flat clusters
+  clusterCount?: number;
+  dynamicCut?: DynamicCutSettings;
+  // report how well each dendrogram fits its distances
+  copheneticCorrelation?: boolean;
+  nanPolicy?: NanPolicy;
+  // applied to the columns before `logTransform` and `scaling`
+  normalization?: Normalization;
+  // log2(x + 1) before `scaling`; the returned values are the scaled ones
+  logTransform?: boolean;
+  scaling?: Scaling;
+  rowLabels?: string[];
+  colLabels?: string[];
 }
 
 export const useCrust = ({
@@ -54,6 +184,19 @@ export const useCrust = ({
   axis,
   linkage,
   distance,
+  minkowskiP,
+  ordering,
+  rowWeights,
+  colWeights,
+  clusterCount,
+  dynamicCut,
+  copheneticCorrelation,
+  nanPolicy,
+  normalization,
+  logTransform,
+  scaling,
+  rowLabels,
+  colLabels,
 }: CrustHookProps) => {
   const crustWorker = useRef<Worker | null>(null);
   const [result, setResult] = useState<HierarchicalClusteringResult | null>(
@@ -98,13 +241,46 @@ export const useCrust = ({
         axis,
         linkage,
         distance,
+        minkowskiP,
+        ordering,
+        rowWeights,
+        colWeights,
+        clusterCount,
+        dynamicCut,
+        copheneticCorrelation,
+        nanPolicy,
+        normalization,
+        logTransform,
+        scaling,
+        rowLabels,
+        colLabels,
       },
     } satisfies CrustWorkerRequest);
 
     return () => {
       crustWorker.current?.terminate();
     };
-  }, [axis, linkage, distance, data, ncols, nrows]);
+  }, [
+    axis,
+    linkage,
+    distance,
+    minkowskiP,
+    ordering,
+    rowWeights,
+    colWeights,
+    clusterCount,
+    dynamicCut,
+    copheneticCorrelation,
+    nanPolicy,
+    normalization,
+    logTransform,
+    scaling,
+    rowLabels,
+    colLabels,
+    data,
+    ncols,
+    nrows,
+  ]);
 
   if (loading) {
     return {
@@ -130,5 +306,144 @@ export const useCrust = ({
     rowOrder: result ? [...result.row_order] : [...Array(nrows).keys()],
     colOrder: result ? [...result.col_order] : [...Array(ncols).keys()],
     values: result ? [...result.values] : data,
+    rowLinkage: result ? [...result.row_linkage] : [],
+    colLinkage: result ? [...result.col_linkage] : [],
+    rowNewick: result?.row_newick,
+    colNewick: result?.col_newick,
+    rowDendrogram: result ? [...result.row_dendrogram] : [],
+    colDendrogram: result ? [...result.col_dendrogram] : [],
+    rowClusters: result?.row_clusters ? [...result.row_clusters] : [],
+    colClusters: result?.col_clusters ? [...result.col_clusters] : [],
+    rowModules: result?.row_modules ? [...result.row_modules] : [],
+    colModules: result?.col_modules ? [...result.col_modules] : [],
+    rowCopheneticCorrelation: result?.row_cophenetic_correlation,
+    colCopheneticCorrelation: result?.col_cophenetic_correlation,
+    unplacedRows: result ? [...result.unplaced_rows] : [],
+    unplacedCols: result ? [...result.unplaced_cols] : [],
+  };
+};
+
+interface CrustDistanceMatrixHookProps {
+  distances: number[];
+  size: number;
+  layout: DistanceMatrixLayout;
+  linkage: LinkageFunction;
+  ordering?: LeafOrdering;
+  weights?: Float64Array;
+  clusterCount?: number;
+  dynamicCut?: DynamicCutSettings;
+  copheneticCorrelation?: boolean;
+  nanPolicy?: NanPolicy;
+  labels?: string[];
+}
+
+// clusters observations from precomputed distances; `values` is the square
+// distance matrix with rows and columns in dendrogram order
+export const useCrustDistanceMatrix = ({
+  distances,
+  size,
+  layout,
+  linkage,
+  ordering,
+  weights,
+  clusterCount,
+  dynamicCut,
+  copheneticCorrelation,
+  nanPolicy,
+  labels,
+}: CrustDistanceMatrixHookProps) => {
+  const crustWorker = useRef<Worker | null>(null);
+  const [result, setResult] = useState<HierarchicalClusteringResult | null>(
+    null
+  );
+  const [loading, setLoading] = useState(true);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    setLoading(true);
+    setResult(null);
+    setError(null);
+
+    crustWorker.current = new Worker(
+      new URL("../../lib/clustering/worker.ts", import.meta.url),
+      {
+        type: "module",
+      }
+    );
+
+    crustWorker.current.onmessage = (
+      event: MessageEvent<CrustWorkerResponse>
+    ) => {
+      const { type, payload } = event.data;
+      if (type === "result") {
+        setResult(payload);
+        setError(null);
+      } else if (type === "error") {
+        setError(payload);
+        setResult(null);
+      }
+      setLoading(false);
+    };
+
+    crustWorker.current.postMessage({
+      type: "distance_matrix_clustering",
+      payload: {
+        size,
+        distances: new Float64Array(distances),
+        layout,
+        linkage,
+        ordering,
+        rowWeights: weights,
+        clusterCount,
+        dynamicCut,
+        copheneticCorrelation,
+        nanPolicy,
+        labels,
+      },
+    } satisfies CrustWorkerRequest);
+
+    return () => {
+      crustWorker.current?.terminate();
+    };
+  }, [
+    distances,
+    size,
+    layout,
+    linkage,
+    ordering,
+    weights,
+    clusterCount,
+    dynamicCut,
+    copheneticCorrelation,
+    nanPolicy,
+    labels,
+  ]);
+
+  if (loading) {
+    return {
+      order: [],
+      values: [],
+    };
+  }
+
+  if (error) {
+    return {
+      order: [...Array(size).keys()],
+      values: [],
+      loading: false,
+      error: error,
+    };
+  }
+
+  return {
+    order: result ? [...result.row_order] : [...Array(size).keys()],
+    values: result ? [...result.values] : [],
+    linkage: result ? [...result.row_linkage] : [],
+    newick: result?.row_newick,
+    dendrogram: result ? [...result.row_dendrogram] : [],
+    clusters: result?.row_clusters ? [...result.row_clusters] : [],
+    modules: result?.row_modules ? [...result.row_modules] : [],
+    copheneticCorrelation: result?.row_cophenetic_correlation,
+    unplaced: result ? [...result.unplaced_rows] : [],
   };
 };
@@ -21,8 +21,34 @@ import { useHeatMapStore } from "./state";
 import D3Tooltip, { TooltipHandle } from "./Tooltip";
 
 type ClusteringAxis = "Row" | "Column" | "Both";
-type LinkageFunction = "Average" | "Ward";
-type DistanceMetric = "Euclidean" | "Chebyshev";
+type LinkageFunction =
+  | "Average"
+  | "Ward"
+  | "Single"
+  | "Complete"
+  | "Weighted"
+  | "Centroid"
+  | "Median";
+type DistanceMetric =
+  | "Euclidean"
+  | "SquaredEuclidean"
+  | "Chebyshev"
+  | "Pearson"
+  | "Spearman"
+  | "Cosine"
+  | "Manhattan"
+  | "Canberra"
+  | "Minkowski";
+type LeafOrdering =
+  | "LadderizeDescending"
+  | "LadderizeAscending"
+  | "MergeOrder"
+  | "MeanValue"
+  | "Optimal";
+type Normalization = "None" | "Quantile" | "MedianOfRatios" | "Tmm";
+
+// `MAX_OPTIMAL_LEAVES` in crust, larger trees are refused
+const MAX_OPTIMAL_LEAVES = 1000;
 
 export const HeatMapVisualizer = () => {
   const tooltipRef = useRef<TooltipHandle>(null);
@@ -46,10 +72,15 @@ export const HeatMapVisualizer = () => {
     useState<ExpressionResponse | null>(null);
   const [scalingFunctionName, setScalingFunctionName] =
     useState<DataScalingOptions>("log");
+  const [normalization, setNormalization] = useState<Normalization>("None");
 
   const [axis, setAxis] = useState<ClusteringAxis>("Row");
   const [linkage, setLinkage] = useState<LinkageFunction>("Average");
   const [distance, setDistance] = useState<DistanceMetric>("Euclidean");
+  const [minkowskiP, setMinkowskiP] = useState<number>(2);
+  const [ordering, setOrdering] = useState<LeafOrdering>(
+    "LadderizeDescending"
+  );
 
   useEffect(() => {
     if (
@@ -277,9 +308,44 @@ export const HeatMapVisualizer = () => {
             }
           >
             <option value={"Euclidean"}>euclidean</option>
+            <option value={"SquaredEuclidean"}>squared euclidean</option>
             <option value={"Chebyshev"}>chebyshev</option>
+            <option value={"Pearson"}>pearson</option>
+            <option value={"Spearman"}>spearman</option>
+            <option value={"Cosine"}>cosine</option>
+            <option value={"Manhattan"}>manhattan</option>
+            <option value={"Canberra"}>canberra</option>
+            <option value={"Minkowski"}>minkowski</option>
           </select>
         </label>
+        {distance === "Minkowski" ? (
+          <label
+            style={{
+              fontStyle: "var(--inter)",
+              display: "flex",
+              flexDirection: "column",
+              fontSize: "0.75rem",
+              fontWeight: "bold",
+              alignItems: "flex-start",
+            }}
+          >
+            p
+            <input
+              type="number"
+              min={0}
+              step={0.5}
+              style={{
+                width: "4em",
+                backgroundColor: "var(--background)",
+                color: "var(--color)",
+                border: "1px solid var(--color)",
+                borderRadius: "var(--radius)",
+              }}
+              value={minkowskiP}
+              onChange={(event) => setMinkowskiP(event.target.valueAsNumber)}
+            />
+          </label>
+        ) : null}
         <label
           style={{
             fontStyle: "var(--inter)",
@@ -307,6 +373,54 @@ export const HeatMapVisualizer = () => {
           >
             <option value={"Average"}>average</option>
             <option value={"Ward"}>ward</option>
+            <option value={"Single"}>single</option>
+            <option value={"Complete"}>complete</option>
+            <option value={"Weighted"}>weighted</option>
+            <option value={"Centroid"}>centroid</option>
+            <option value={"Median"}>median</option>
+          </select>
+        </label>
+        <label
+          style={{
+            fontStyle: "var(--inter)",
+            display: "flex",
+            flexDirection: "column",
+            fontSize: "0.75rem",
+            fontWeight: "bold",
+            alignItems: "flex-start",
+          }}
+        >
+          Ordering
+          <select
+            style={{
+              paddingRight: "1em",
+              textAlign: "left",
+              backgroundColor: "var(--background)",
+              color: "var(--color)",
+              border: "1px solid var(--color)",
+              borderRadius: "var(--radius)",
+            }}
+            value={ordering}
+            onChange={(event) =>
+              setOrdering(event.target.value as LeafOrdering)
+            }
+          >
+            <option value={"LadderizeDescending"}>larger first</option>
+            <option value={"LadderizeAscending"}>smaller first</option>
+            <option value={"MergeOrder"}>merge order</option>
+            <option value={"MeanValue"}>mean expression</option>
+            <option
+              value={"Optimal"}
+              disabled={
+                expressionData !== null &&
+                Math.max(
+                  expressionData.geneIds.length,
+                  expressionData.samples.length
+                ) > MAX_OPTIMAL_LEAVES
+              }
+            >
+              optimal
+            </option>
           </select>
         </label>
         <label
@@ -337,6 +451,37 @@ export const HeatMapVisualizer = () => {
             <option value={"Both"}>both</option>
           </select>
         </label>
+        <label
+          style={{
+            fontStyle: "var(--inter)",
+            display: "flex",
+            flexDirection: "column",
+            fontSize: "0.75rem",
+            fontWeight: "bold",
+            alignItems: "flex-start",
+          }}
+        >
+          Normalisation
+          <select
+            style={{
+              paddingRight: "1em",
+              textAlign: "left",
+              backgroundColor: "var(--background)",
+              color: "var(--color)",
+              border: "1px solid var(--color)",
+              borderRadius: "var(--radius)",
+            }}
+            value={normalization}
+            onChange={(event) =>
+              setNormalization(event.target.value as Normalization)
+            }
+          >
+            <option value={"None"}>none</option>
+            <option value={"Quantile"}>quantile</option>
+            <option value={"MedianOfRatios"}>median of ratios</option>
+            <option value={"Tmm"}>TMM</option>
+          </select>
+        </label>
         <label
           style={{
             fontStyle: "var(--inter)",
@@ -407,9 +552,12 @@ export const HeatMapVisualizer = () => {
             cellHeight={15}
             cellPadding={1}
             scalingFunctionName={scalingFunctionName}
+            normalization={normalization}
             clusterAxis={axis}
             distanceMetric={distance}
+            minkowskiP={distance === "Minkowski" ? minkowskiP : undefined}
             clusterLinkage={linkage}
+            leafOrdering={ordering}
           />
         ) : null}
       </div>
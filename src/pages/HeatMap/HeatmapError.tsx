@@ -8,3 +8,12 @@ export const NoGeneListsErrorComponent = () => {
     </div>
   );
 };
+
+export const ClusteringErrorComponent = ({ message }: { message: string }) => {
+  return (
+    <div style={{ textAlign: "center" }}>
+      <p style={{ fontWeight: "bold" }}>The heatmap could not be clustered.</p>
+      <p>{message}</p>
+    </div>
+  );
+};
@@ -17,14 +17,17 @@ import { useHeatMapStore, setSvgHeight, setSvgWidth } from "./state";
 
 import styles from "./SvgHeatMap.module.css";
 import { TooltipHandle } from "./Tooltip";
+import { ClusteringErrorComponent } from "./HeatmapError";
 
 import {
   useCrust,
   ClusteringAxis,
   LinkageFunction,
   DistanceMetric,
+  LeafOrdering,
+  Normalization,
 } from "../../lib/hooks/useCrust";
-import { DataScalers, DataScalingOptions } from "../../lib/scaling";
+import { CrustScalings, DataScalingOptions } from "../../lib/scaling";
 
 interface SvgHeatMapProps {
   svgRef: RefObject<SVGSVGElement | null>;
@@ -39,9 +42,13 @@ interface SvgHeatMapProps {
   cellHeight: number;
   expressionData: ExpressionResponse;
   scalingFunctionName: DataScalingOptions;
+  normalization: Normalization;
   distanceMetric: DistanceMetric;
+  // exponent of the Minkowski metric, checked by crust
+  minkowskiP?: number;
   clusterAxis: ClusteringAxis;
   clusterLinkage: LinkageFunction;
+  leafOrdering: LeafOrdering;
 }
 
 interface RectHoverData {
@@ -62,9 +69,12 @@ export const SvgHeatMap = ({
   labelFontSize,
   labelPadding,
   scalingFunctionName,
+  normalization,
   distanceMetric,
+  minkowskiP,
   clusterAxis,
   clusterLinkage,
+  leafOrdering,
   cellHeight,
   cellPadding,
 }: SvgHeatMapProps) => {
@@ -87,7 +97,7 @@ export const SvgHeatMap = ({
           <strong>Original</strong>: {data.value.toFixed(3)}
         </div>
         <div>
-          <strong>Scaled</strong>: {(1 - data.scaledValue).toFixed(3)}
+          <strong>Scaled</strong>: {data.scaledValue.toFixed(3)}
         </div>
       </>,
       event.pageX,
@@ -197,23 +207,24 @@ export const SvgHeatMap = ({
   //   distanceMetric,
   // });
 
-  const scaledData = useMemo(
-    () =>
-      DataScalers[scalingFunctionName].function({
-        data: expressionData.values,
-        nrows: rowLabels.length,
-        ncols: colLabels.length,
-      }),
-    [rowLabels, colLabels, expressionData, scalingFunctionName]
-  );
-
-  const { rowOrder, colOrder, values } = useCrust({
-    data: scaledData,
+  const {
+    rowOrder,
+    colOrder,
+    values,
+    error: clusteringError,
+  } = useCrust({
+    data: expressionData.values,
     nrows: rowLabels.length,
     ncols: colLabels.length,
     axis: clusterAxis,
     linkage: clusterLinkage,
     distance: distanceMetric,
+    minkowskiP,
+    ordering: leafOrdering,
+    // sequencing depth is normalised away before scaling
+    normalization,
+    // clustered and displayed values are scaled alike by crust
+    ...CrustScalings[scalingFunctionName],
   });
 
   const reorderedRowMap = useMemo(
@@ -262,13 +273,23 @@ export const SvgHeatMap = ({
     [heatmapBounds.top, heatmapBounds.bottom, rowLabels.length]
   );
 
-  const colorScale = useMemo(
-    () =>
-      scaleLinear()
-        .domain([Math.max(...values), Math.min(...values)])
-        .range([0, 1]),
-    [values]
-  );
+  // no spread into Math.min/max, which overflows the stack on large
+  // matrices; high values map to the red end of RdYlBu
+  const colorScale = useMemo(() => {
+    let min = Infinity;
+    let max = -Infinity;
+    for (const value of values) {
+      if (!Number.isNaN(value)) {
+        min = Math.min(min, value);
+        max = Math.max(max, value);
+      }
+    }
+    return scaleLinear().domain([max, min]).range([0, 1]);
+  }, [values]);
+
+  if (clusteringError) {
+    return <ClusteringErrorComponent message={clusteringError} />;
+  }
 
   if (
     !(
@@ -343,7 +364,7 @@ export const SvgHeatMap = ({
             fill={
               Number.isNaN(values[index])
                 ? gray(50).toString()
-                : interpolateRdYlBu(values[index])
+                : interpolateRdYlBu(colorScale(values[index]))
             }
             onMouseOver={(e) =>
               handleMouseOver(e, {
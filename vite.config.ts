@@ -1,8 +1,17 @@
 import { defineConfig } from 'vite'
 import react from '@vitejs/plugin-react'
 
+// cross-origin isolation, without it there is no SharedArrayBuffer for the
+// threads of a crust build with the `parallel` feature
+const crossOriginIsolation = {
+  'Cross-Origin-Opener-Policy': 'same-origin',
+  'Cross-Origin-Embedder-Policy': 'require-corp',
+}
+
 // https://vite.dev/config/
 export default defineConfig({
   plugins: [react()],
   assetsInclude: ['**/*.wasm'],
+  server: { headers: crossOriginIsolation },
+  preview: { headers: crossOriginIsolation },
 })